@@ -0,0 +1,102 @@
+// api.rs
+// JSON 接口
+use actix_web::{get, web::Data, HttpRequest, HttpResponse, Responder};
+use log::{debug, error};
+use serde::Serialize;
+
+use crate::config::YamlConfig;
+use crate::health::{channel_status, ChannelStatus};
+use crate::iptv::get_cached_channels;
+use crate::login::{login_status, LoginStatus};
+use crate::multicast::{active_groups, GroupStatus};
+use crate::probe::{cached_info, StreamInfo};
+use crate::sign::check_local;
+
+#[derive(Debug, Serialize)]
+pub struct ChannelItem {
+    pub id: u64,
+    pub user_channel_id: String,
+    pub name: String,
+    pub igmp: String,
+    pub rtsp: String,
+    /// 提供该频道的 IPTV 账号
+    pub account: String,
+    pub stream: Option<StreamInfo>,
+}
+
+#[get("/api/channels")]
+pub async fn channel_list(config: Data<YamlConfig>, req: HttpRequest) -> impl Responder {
+    debug!("Get channels api");
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+
+    match get_cached_channels(&config.iptv).await {
+        Ok(channels) => {
+            let items: Vec<ChannelItem> = channels
+                .into_iter()
+                .map(|c| ChannelItem {
+                    stream: cached_info(c.id),
+                    id: c.id,
+                    user_channel_id: c.user_channel_id,
+                    name: c.name,
+                    igmp: c.igmp,
+                    rtsp: c.rtsp,
+                    account: config.iptv.account(c.account).user.clone(),
+                })
+                .collect();
+            HttpResponse::Ok().json(items)
+        }
+        Err(e) => {
+            error!("获取频道列表失败: {}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": e.to_string() }))
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChannelStatusItem {
+    pub id: u64,
+    pub name: String,
+    pub igmp: String,
+    pub status: Option<ChannelStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusResponse {
+    pub channels: Vec<ChannelStatusItem>,
+    pub groups: Vec<GroupStatus>,
+    /// 每个 IPTV 账号的登录会话
+    pub login: Vec<LoginStatus>,
+}
+
+#[get("/api/status")]
+pub async fn status(config: Data<YamlConfig>, req: HttpRequest) -> impl Responder {
+    debug!("Get status api");
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+
+    let channels = match get_cached_channels(&config.iptv).await {
+        Ok(channels) => channels
+            .into_iter()
+            .map(|c| ChannelStatusItem {
+                status: channel_status(c.id),
+                id: c.id,
+                name: c.name,
+                igmp: c.igmp,
+            })
+            .collect(),
+        Err(e) => {
+            error!("获取频道列表失败: {}", e);
+            Vec::new()
+        }
+    };
+
+    HttpResponse::Ok().json(StatusResponse {
+        channels,
+        groups: active_groups(),
+        login: config.iptv.iter().map(login_status).collect(),
+    })
+}
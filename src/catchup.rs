@@ -0,0 +1,427 @@
+// catchup.rs
+// 回看跳转：把播放器的各种回看参数（utc/lutc、start/end、timestamp/duration、Flussonic）转换为上游的 playseek 后重定向
+use actix_web::{
+    get,
+    http::header,
+    web::{Data, Path, Query},
+    HttpRequest, HttpResponse, Responder,
+};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone};
+use log::debug;
+use serde::Deserialize;
+
+use crate::config::YamlConfig;
+use crate::iptv::find_channel;
+use crate::routes::{base_url, catchup_url};
+use crate::rtsp::{format_playseek, parse_playseek};
+use crate::sign::{check_signature, sign_url};
+
+/// 播放器传入的回看参数
+#[derive(Debug, Default, Deserialize)]
+pub struct CatchupQuery {
+    /// 上游格式 yyyyMMddHHmmss-yyyyMMddHHmmss（北京时间），原样使用
+    pub playseek: Option<String>,
+    /// 开始时间（Unix 时间戳），Kodi / TiviMate 的 {utc}
+    pub utc: Option<String>,
+    /// 播放器的当前时间（Unix 时间戳），回看开始时间不能晚于它
+    pub lutc: Option<String>,
+    /// 开始 / 结束时间：Unix 时间戳、yyyyMMddHHmmss 或 RFC 3339
+    pub start: Option<String>,
+    pub end: Option<String>,
+    /// 开始时间（Unix 时间戳）
+    pub timestamp: Option<String>,
+    /// 时长（秒），和 utc / timestamp / start / from 一起使用
+    pub duration: Option<String>,
+    /// Flussonic 的开始时间（Unix 时间戳）
+    pub from: Option<String>,
+    /// yyyyMMddHHmmss 时间使用的时区，如 8 或 +08:00，默认使用 catchup.timezone
+    pub tz: Option<String>,
+}
+
+impl CatchupQuery {
+    /// 是否带有回看时间
+    pub(crate) fn has_time(&self) -> bool {
+        self.playseek.is_some()
+            || self.utc.is_some()
+            || self.start.is_some()
+            || self.timestamp.is_some()
+            || self.from.is_some()
+    }
+}
+
+/// 时区偏移的范围（秒），±14 小时
+const MAX_TZ_OFFSET: i32 = 14 * 60 * 60;
+
+/// 按小时数得到时区，超出 ±14 小时时返回 None
+pub(crate) fn tz_from_hours(hours: i32) -> Option<FixedOffset> {
+    hours
+        .checked_mul(60 * 60)
+        .filter(|secs| secs.abs() <= MAX_TZ_OFFSET)
+        .and_then(FixedOffset::east_opt)
+}
+
+/// 解析时区：小时数或 ±HH:MM
+fn parse_tz(tz: &str) -> Result<FixedOffset> {
+    // 查询参数中未转义的 + 会被解码成空格
+    let tz = tz.replace(' ', "+");
+    let tz = tz.trim();
+    let offset = match tz.parse::<i32>() {
+        Ok(hours) => tz_from_hours(hours),
+        Err(_) => tz
+            .parse::<FixedOffset>()
+            .ok()
+            .filter(|offset| offset.local_minus_utc().abs() <= MAX_TZ_OFFSET),
+    };
+    offset.ok_or(anyhow!("无效的时区 {}", tz))
+}
+
+/// 秒转换为毫秒，溢出时返回错误
+fn secs_to_millis(secs: i64) -> Result<i64> {
+    secs.checked_mul(1000).ok_or(anyhow!("无效的时间 {}", secs))
+}
+
+/// 解析时间，返回毫秒时间戳
+///
+/// 支持 Unix 时间戳（秒或毫秒）、yyyyMMddHHmmss（按 tz 时区）和 RFC 3339。
+fn parse_time(value: &str, tz: FixedOffset) -> Result<i64> {
+    let value = value.trim();
+    if value.len() == 14 && value.bytes().all(|b| b.is_ascii_digit()) {
+        let local = NaiveDateTime::parse_from_str(value, "%Y%m%d%H%M%S")
+            .with_context(|| format!("无法解析时间 {}", value))?;
+        return Ok(tz
+            .from_local_datetime(&local)
+            .single()
+            .ok_or(anyhow!("无效的时间 {}", value))?
+            .timestamp_millis());
+    }
+    if let Ok(timestamp) = value.parse::<i64>() {
+        // 13 位时间戳为毫秒
+        return if value.len() >= 13 {
+            Ok(timestamp)
+        } else {
+            secs_to_millis(timestamp)
+        };
+    }
+    DateTime::parse_from_rfc3339(&value.replace(' ', "+"))
+        .map(|t| t.timestamp_millis())
+        .with_context(|| format!("无法解析时间 {}", value))
+}
+
+/// 解析 Flussonic 的回看文件名，返回毫秒时间戳
+///
+/// archive-{from}-{duration}.ts、index-{from}-{duration}.m3u8、video-{from}-{duration}.m3u8、
+/// timeshift_abs-{from}.ts，duration 为 now 时不限结束时间。
+fn parse_flussonic(file: &str) -> Result<(i64, Option<i64>)> {
+    let invalid = || anyhow!("无法识别的回看地址 {}", file);
+    let stem = file.split('.').next().unwrap_or(file);
+    let (kind, rest) = stem.split_once('-').ok_or_else(invalid)?;
+    let mut parts = rest.split('-');
+    let from: i64 = parts
+        .next()
+        .and_then(|p| p.parse().ok())
+        .ok_or_else(invalid)?;
+
+    match kind {
+        "timeshift_abs" => Ok((secs_to_millis(from)?, None)),
+        "archive" | "index" | "video" | "mono" => match parts.next() {
+            Some("now") => Ok((secs_to_millis(from)?, None)),
+            Some(duration) => {
+                let duration: i64 = duration.parse().map_err(|_| invalid())?;
+                let end = from.checked_add(duration).ok_or_else(invalid)?;
+                Ok((secs_to_millis(from)?, Some(secs_to_millis(end)?)))
+            }
+            None => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// 从查询参数计算回看的起止时间（毫秒）
+fn query_range(query: &CatchupQuery, tz: FixedOffset) -> Result<(i64, Option<i64>)> {
+    if let Some(playseek) = &query.playseek {
+        return parse_playseek(playseek);
+    }
+
+    let start = query
+        .utc
+        .as_ref()
+        .or(query.timestamp.as_ref())
+        .or(query.start.as_ref())
+        .or(query.from.as_ref())
+        .ok_or(anyhow!("缺少回看开始时间"))?;
+    let start = parse_time(start, tz)?;
+
+    if let Some(now) = &query.lutc {
+        if start > parse_time(now, tz)? {
+            return Err(anyhow!("回看开始时间晚于当前时间"));
+        }
+    }
+
+    let end = match (&query.end, &query.duration) {
+        (Some(end), _) => Some(parse_time(end, tz)?),
+        (None, Some(duration)) => {
+            let duration: i64 = duration
+                .trim()
+                .parse()
+                .with_context(|| format!("无效的时长 {}", duration))?;
+            Some(
+                secs_to_millis(duration)?
+                    .checked_add(start)
+                    .ok_or(anyhow!("无效的时长 {}", duration))?,
+            )
+        }
+        (None, None) => None,
+    };
+    Ok((start, end))
+}
+
+/// 重定向到带 playseek 的上游回看地址
+async fn redirect(
+    config: &YamlConfig,
+    channel_id: u64,
+    range: Result<(i64, Option<i64>)>,
+    req: &HttpRequest,
+) -> HttpResponse {
+    let (start, end) = match range {
+        Ok((start, Some(end))) if end <= start => {
+            return HttpResponse::BadRequest().body("结束时间早于开始时间")
+        }
+        Ok(range) => range,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let channel = match find_channel(&config.iptv, channel_id).await {
+        Ok(channel) => channel,
+        Err(e) => return HttpResponse::NotFound().body(e.to_string()),
+    };
+    let source = catchup_url(config, &channel, &base_url(req));
+    if source.is_empty() {
+        return HttpResponse::NotFound().body(format!("频道 {} 没有回看地址", channel.name));
+    }
+
+    let playseek = match format_playseek(start, end) {
+        Some(playseek) => playseek,
+        None => return HttpResponse::BadRequest().body("无效的回看时间"),
+    };
+    let connector = if source.contains('?') { "&" } else { "?" };
+    let url = sign_url(
+        config,
+        &format!("{}{}playseek={}", source, connector, playseek),
+        req,
+    );
+    debug!("频道 {} 回看重定向到 {}", channel.name, url);
+
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .finish()
+}
+
+/// 查询参数中的时区，默认使用 catchup.timezone
+fn query_tz(config: &YamlConfig, query: &CatchupQuery) -> Result<FixedOffset> {
+    match &query.tz {
+        Some(tz) => parse_tz(tz),
+        None => tz_from_hours(config.catchup.timezone)
+            .ok_or(anyhow!("无效的时区 {}", config.catchup.timezone)),
+    }
+}
+
+#[get("/catchup/{channel_id}")]
+pub async fn catchup(
+    config: Data<YamlConfig>,
+    path: Path<u64>,
+    query: Query<CatchupQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let channel_id = path.into_inner();
+    debug!("回看频道 {}: {:?}", channel_id, query);
+    if let Some(response) = check_signature(&config, &req) {
+        return response;
+    }
+
+    let range = query_tz(&config, &query).and_then(|tz| query_range(&query, tz));
+    redirect(&config, channel_id, range, &req).await
+}
+
+#[get("/catchup/{channel_id}/{file}")]
+pub async fn catchup_flussonic(
+    config: Data<YamlConfig>,
+    path: Path<(u64, String)>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (channel_id, file) = path.into_inner();
+    debug!("回看频道 {}: {}", channel_id, file);
+    if let Some(response) = check_signature(&config, &req) {
+        return response;
+    }
+
+    redirect(&config, channel_id, parse_flussonic(&file), &req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01T00:00:00Z
+    const T0: i64 = 1_704_067_200;
+
+    fn beijing() -> FixedOffset {
+        tz_from_hours(8).unwrap()
+    }
+
+    #[test]
+    fn parse_tz_hours_and_offsets() {
+        assert_eq!(parse_tz("8").unwrap().local_minus_utc(), 8 * 3600);
+        assert_eq!(parse_tz("-5").unwrap().local_minus_utc(), -5 * 3600);
+        assert_eq!(parse_tz("+08:00").unwrap().local_minus_utc(), 8 * 3600);
+        // 未转义的 + 被解码成空格
+        assert_eq!(
+            parse_tz(" 05:30").unwrap().local_minus_utc(),
+            5 * 3600 + 1800
+        );
+        assert_eq!(parse_tz("14").unwrap().local_minus_utc(), 14 * 3600);
+    }
+
+    #[test]
+    fn parse_tz_rejects_out_of_range() {
+        assert!(parse_tz("15").is_err());
+        assert!(parse_tz("-15").is_err());
+        assert!(parse_tz("2147483647").is_err());
+        assert!(parse_tz("-2147483648").is_err());
+        assert!(parse_tz("+20:00").is_err());
+        assert!(parse_tz("abc").is_err());
+        assert!(tz_from_hours(i32::MAX).is_none());
+    }
+
+    #[test]
+    fn parse_time_formats() {
+        let tz = beijing();
+        assert_eq!(parse_time("1704067200", tz).unwrap(), T0 * 1000);
+        assert_eq!(parse_time("1704067200123", tz).unwrap(), T0 * 1000 + 123);
+        assert_eq!(parse_time("20240101080000", tz).unwrap(), T0 * 1000);
+        assert_eq!(
+            parse_time("20240101000000", tz_from_hours(0).unwrap()).unwrap(),
+            T0 * 1000
+        );
+        assert_eq!(
+            parse_time("2024-01-01T08:00:00+08:00", tz).unwrap(),
+            T0 * 1000
+        );
+        // 查询参数中的 + 被解码成空格
+        assert_eq!(
+            parse_time("2024-01-01T08:00:00 08:00", tz).unwrap(),
+            T0 * 1000
+        );
+        assert!(parse_time("20241301000000", tz).is_err());
+        assert!(parse_time("yesterday", tz).is_err());
+    }
+
+    #[test]
+    fn secs_to_millis_overflow() {
+        assert_eq!(secs_to_millis(T0).unwrap(), T0 * 1000);
+        assert!(secs_to_millis(i64::MAX).is_err());
+        assert!(secs_to_millis(i64::MIN).is_err());
+    }
+
+    #[test]
+    fn query_utc_lutc() {
+        let query = CatchupQuery {
+            utc: Some(T0.to_string()),
+            lutc: Some((T0 + 3600).to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query_range(&query, beijing()).unwrap(), (T0 * 1000, None));
+
+        let query = CatchupQuery {
+            utc: Some(T0.to_string()),
+            lutc: Some((T0 - 1).to_string()),
+            ..Default::default()
+        };
+        assert!(query_range(&query, beijing()).is_err());
+    }
+
+    #[test]
+    fn query_start_end() {
+        let query = CatchupQuery {
+            start: Some("20240101080000".to_string()),
+            end: Some("20240101090000".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            query_range(&query, beijing()).unwrap(),
+            (T0 * 1000, Some((T0 + 3600) * 1000))
+        );
+    }
+
+    #[test]
+    fn query_timestamp_duration() {
+        let query = CatchupQuery {
+            timestamp: Some(T0.to_string()),
+            duration: Some("1800".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            query_range(&query, beijing()).unwrap(),
+            (T0 * 1000, Some((T0 + 1800) * 1000))
+        );
+
+        let query = CatchupQuery {
+            timestamp: Some(T0.to_string()),
+            duration: Some(i64::MAX.to_string()),
+            ..Default::default()
+        };
+        assert!(query_range(&query, beijing()).is_err());
+
+        let query = CatchupQuery {
+            duration: Some("1800".to_string()),
+            ..Default::default()
+        };
+        assert!(query_range(&query, beijing()).is_err());
+    }
+
+    #[test]
+    fn query_playseek_passthrough() {
+        let query = CatchupQuery {
+            playseek: Some("20240101080000-20240101090000".to_string()),
+            // playseek 固定为北京时间，不受 tz 影响
+            tz: Some("0".to_string()),
+            ..Default::default()
+        };
+        let range = query_range(&query, tz_from_hours(0).unwrap()).unwrap();
+        assert_eq!(range, (T0 * 1000, Some((T0 + 3600) * 1000)));
+        assert_eq!(
+            format_playseek(range.0, range.1).unwrap(),
+            "20240101080000-20240101090000"
+        );
+    }
+
+    #[test]
+    fn flussonic_paths() {
+        assert_eq!(
+            parse_flussonic("archive-1704067200-3600.ts").unwrap(),
+            (T0 * 1000, Some((T0 + 3600) * 1000))
+        );
+        assert_eq!(
+            parse_flussonic("index-1704067200-now.m3u8").unwrap(),
+            (T0 * 1000, None)
+        );
+        assert_eq!(
+            parse_flussonic("video-1704067200-60.m3u8").unwrap(),
+            (T0 * 1000, Some((T0 + 60) * 1000))
+        );
+        assert_eq!(
+            parse_flussonic("timeshift_abs-1704067200.ts").unwrap(),
+            (T0 * 1000, None)
+        );
+    }
+
+    #[test]
+    fn flussonic_invalid() {
+        assert!(parse_flussonic("index.m3u8").is_err());
+        assert!(parse_flussonic("archive-abc-60.ts").is_err());
+        assert!(parse_flussonic("archive-1704067200.ts").is_err());
+        assert!(parse_flussonic("live-1704067200-60.ts").is_err());
+        assert!(parse_flussonic(&format!("archive-{}-1.ts", i64::MAX)).is_err());
+        assert!(parse_flussonic(&format!("timeshift_abs-{}.ts", i64::MAX)).is_err());
+    }
+}
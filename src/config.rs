@@ -1,13 +1,37 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::collections::HashMap;
+use std::ops::Deref;
 
 /// 主配置文件结构
 #[derive(Debug, Deserialize, Clone)]
 pub struct YamlConfig {
     pub server: ServerConfig,
-    pub iptv: IptvConfig,
+    pub iptv: IptvAccounts,
     pub m3u8: M3u8Config,
+    #[serde(default)]
+    pub relay: RelayConfig,
+    #[serde(default)]
+    pub hls: HlsConfig,
+    #[serde(default)]
+    pub probe: ProbeConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub dvr: DvrConfig,
+    #[serde(default)]
+    pub timeshift: TimeshiftConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub play: PlayConfig,
+    #[serde(default)]
+    pub catchup: CatchupConfig,
+    #[serde(default)]
+    pub sign: SignConfig,
+    /// 组播转单播的转发目标
+    #[serde(default)]
+    pub forward: Vec<ForwardTarget>,
     pub name_mapping: Option<HashMap<String, String>>,
     #[serde(default)]  // 允许该字段不存在
     pub name_clean: Vec<String>,  // 直接是字符串数组，不是嵌套结构
@@ -47,10 +71,139 @@ fn default_log_level() -> String {
 pub struct IptvConfig {
     pub user: String,
     pub passwd: String,
+    /// 认证使用的 MAC 地址，为空时使用 interface 网卡的 MAC 地址
+    #[serde(default)]
     pub mac: String,
     pub imei: Option<String>,
+    /// 认证使用的 IP 地址，为空时使用 interface 网卡当前的 IPv4 地址
     pub ip: Option<String>,
     pub interface: Option<String>,
+
+    /// IPTV 平台
+    #[serde(default)]
+    pub provider: ProviderKind,
+
+    /// EDS 地址，按顺序尝试，为空时使用平台默认地址
+    #[serde(default)]
+    pub eds_urls: Vec<String>,
+
+    /// 固定的 EPG 服务器地址，设置后不再通过 EDS 获取
+    pub epg_url: Option<String>,
+
+    /// 登录会话有效期（秒）
+    #[serde(default = "default_iptv_session_ttl")]
+    pub session_ttl: u64,
+
+    /// 会话过期前多久主动重新登录（秒）
+    #[serde(default = "default_iptv_session_refresh")]
+    pub session_refresh: u64,
+
+    /// 在 iptv 账号列表中的序号，加载配置时填入
+    #[serde(skip)]
+    pub index: usize,
+}
+
+/// IPTV 平台，新增平台时在 provider 模块中实现并在这里添加
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// 广东电信（华为 EPG）
+    #[default]
+    Guangdong,
+}
+
+/// 登录会话有效期的下限（秒），太短时后台会不停地重新登录
+const MIN_IPTV_SESSION_TTL: u64 = 60;
+
+fn default_iptv_session_ttl() -> u64 {
+    1800
+}
+
+fn default_iptv_session_refresh() -> u64 {
+    300
+}
+
+/// IPTV 账号：可以是单个账号，也可以是账号列表
+///
+/// 多个账号的频道列表会合并去重，排在前面的账号优先。
+#[derive(Debug, Clone)]
+pub struct IptvAccounts(Vec<IptvConfig>);
+
+impl IptvAccounts {
+    /// 第几个账号，超出范围时返回第一个账号
+    pub fn account(&self, index: usize) -> &IptvConfig {
+        self.0.get(index).unwrap_or(&self.0[0])
+    }
+
+    /// 第几个账号使用的网卡
+    pub fn interface(&self, index: usize) -> Option<&str> {
+        self.account(index).interface.as_deref()
+    }
+}
+
+impl Deref for IptvAccounts {
+    type Target = [IptvConfig];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for IptvAccounts {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+        use serde::de::{MapAccess, SeqAccess, Visitor};
+
+        // 按实际的形式（单个账号或列表）直接反序列化，保留字段级的错误信息
+        struct OneOrMany;
+
+        impl<'de> Visitor<'de> for OneOrMany {
+            type Value = Vec<IptvConfig>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("一个账号或账号列表")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                IptvConfig::deserialize(MapAccessDeserializer::new(map)).map(|a| vec![a])
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Vec::deserialize(SeqAccessDeserializer::new(seq))
+            }
+        }
+
+        let mut accounts = deserializer.deserialize_any(OneOrMany)?;
+        if accounts.is_empty() {
+            return Err(serde::de::Error::custom("iptv 至少需要一个账号"));
+        }
+        if let Some(account) = accounts
+            .iter()
+            .find(|a| a.session_ttl < MIN_IPTV_SESSION_TTL)
+        {
+            return Err(serde::de::Error::custom(format!(
+                "账号 {} 的 session_ttl 不能小于 {} 秒",
+                account.user, MIN_IPTV_SESSION_TTL
+            )));
+        }
+        // 同一个用户名在同一张网卡上只能登录一次
+        for (index, account) in accounts.iter().enumerate() {
+            if accounts[..index]
+                .iter()
+                .any(|a| a.user == account.user && a.interface == account.interface)
+            {
+                return Err(serde::de::Error::custom(format!(
+                    "账号 {} 在网卡 {} 上重复配置",
+                    account.user,
+                    account.interface.as_deref().unwrap_or("默认")
+                )));
+            }
+        }
+        for (index, account) in accounts.iter_mut().enumerate() {
+            account.index = index;
+        }
+        Ok(Self(accounts))
+    }
 }
 
 #[derive(Debug, Deserialize, Clone, Default)]
@@ -69,8 +222,551 @@ pub struct M3u8Config {
     
     #[serde(default)]
     pub rtsp_proxy_uri: String,
+
+    /// 播放列表中直播地址的形式
+    #[serde(default)]
+    pub play_url_style: PlayUrlStyle,
+}
+
+/// 播放列表中直播地址的形式
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayUrlStyle {
+    /// 组播地址（或经 udp_proxy_uri 转发）
+    #[default]
+    Multicast,
+    /// 本服务提供的 HLS 地址
+    Hls,
+    /// 本服务的 /play/{channel_id}，组播地址变化后仍然有效
+    Play,
+    /// 本服务的 /play/by-name/{tvgname}，频道 ID 变化后仍然有效
+    #[serde(rename = "play-by-name")]
+    PlayByName,
+}
+
+/// 内置组播转发配置
+#[derive(Debug, Deserialize, Clone)]
+pub struct RelayConfig {
+    /// 组播无数据超时（秒），超时后断开客户端
+    #[serde(default = "default_relay_timeout")]
+    pub timeout: u64,
+
+    /// 组播 socket 接收缓冲区大小（字节）
+    #[serde(default = "default_relay_recv_buffer")]
+    pub recv_buffer: usize,
+
+    /// 每个组播组共享环形缓冲区的容量（包数）
+    #[serde(default = "default_relay_buffer_packets")]
+    pub buffer_packets: usize,
+
+    /// 最后一个客户端离开后，保留组播成员关系的时间（秒）
+    #[serde(default = "default_relay_idle_grace")]
+    pub idle_grace: u64,
+
+    /// RTP 重排窗口（包数）
+    #[serde(default = "default_relay_reorder_window")]
+    pub reorder_window: usize,
+
+    /// 客户端跟不上时的处理方式
+    #[serde(default)]
+    pub lag_policy: LagPolicy,
+
+    /// 缓存每个组播组最近的 GOP，新客户端可以立即开始解码
+    #[serde(default = "default_true")]
+    pub gop_cache: bool,
+
+    /// 单个 GOP 缓存的最大字节数，超过后放弃缓存直到下一个关键帧
+    #[serde(default = "default_relay_gop_cache_max")]
+    pub gop_cache_max: usize,
+
+    /// 同时加入的组播组上限，0 表示不限制
+    #[serde(default)]
+    pub max_groups: usize,
+
+    /// 每个客户端 IP 同时观看的流数量上限，0 表示不限制
+    #[serde(default)]
+    pub max_streams_per_client: usize,
+
+    /// 所有组播组的总码率上限（kbps），0 表示不限制
+    #[serde(default)]
+    pub max_bitrate: u64,
+}
+
+/// 慢客户端处理策略
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LagPolicy {
+    /// 跳过落后的数据，从最新位置继续
+    #[default]
+    Skip,
+    /// 直接断开
+    Drop,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_relay_timeout(),
+            recv_buffer: default_relay_recv_buffer(),
+            buffer_packets: default_relay_buffer_packets(),
+            idle_grace: default_relay_idle_grace(),
+            reorder_window: default_relay_reorder_window(),
+            lag_policy: LagPolicy::default(),
+            gop_cache: true,
+            gop_cache_max: default_relay_gop_cache_max(),
+            max_groups: 0,
+            max_streams_per_client: 0,
+            max_bitrate: 0,
+        }
+    }
+}
+
+fn default_relay_timeout() -> u64 {
+    5
+}
+
+fn default_relay_recv_buffer() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_relay_buffer_packets() -> usize {
+    2048
+}
+
+fn default_relay_idle_grace() -> u64 {
+    10
+}
+
+fn default_relay_reorder_window() -> usize {
+    16
+}
+
+fn default_relay_gop_cache_max() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// HLS 输出配置
+#[derive(Debug, Deserialize, Clone)]
+pub struct HlsConfig {
+    /// 目标分片时长（秒），在关键帧处切分
+    #[serde(default = "default_hls_segment_duration")]
+    pub segment_duration: u64,
+
+    /// 滑动窗口保留的分片数
+    #[serde(default = "default_hls_segment_count")]
+    pub segment_count: usize,
+
+    /// 没有播放器访问多久后停止切片（秒）
+    #[serde(default = "default_hls_idle_timeout")]
+    pub idle_timeout: u64,
 }
 
+impl Default for HlsConfig {
+    fn default() -> Self {
+        Self {
+            segment_duration: default_hls_segment_duration(),
+            segment_count: default_hls_segment_count(),
+            idle_timeout: default_hls_idle_timeout(),
+        }
+    }
+}
+
+fn default_hls_segment_duration() -> u64 {
+    4
+}
+
+fn default_hls_segment_count() -> usize {
+    6
+}
+
+fn default_hls_idle_timeout() -> u64 {
+    30
+}
+
+/// 频道探测配置（读取 PAT/PMT/SDT）
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProbeConfig {
+    /// 是否在后台定期探测所有频道
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// 两轮探测之间的间隔（秒）
+    #[serde(default = "default_probe_interval")]
+    pub interval: u64,
+
+    /// 单个频道的探测时长上限（秒）
+    #[serde(default = "default_probe_timeout")]
+    pub timeout: u64,
+
+    /// 相邻两个频道之间的等待时间（秒），避免同时加入过多组播
+    #[serde(default = "default_probe_delay")]
+    pub delay: u64,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: default_probe_interval(),
+            timeout: default_probe_timeout(),
+            delay: default_probe_delay(),
+        }
+    }
+}
+
+fn default_probe_interval() -> u64 {
+    86400
+}
+
+fn default_probe_timeout() -> u64 {
+    3
+}
+
+fn default_probe_delay() -> u64 {
+    1
+}
+
+/// 组播存活检测配置
+#[derive(Debug, Deserialize, Clone)]
+pub struct HealthConfig {
+    /// 是否在后台定期检测
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// 两轮检测之间的间隔（秒）
+    #[serde(default = "default_health_interval")]
+    pub interval: u64,
+
+    /// 单个频道等待数据的时间（秒）
+    #[serde(default = "default_health_timeout")]
+    pub timeout: u64,
+
+    /// 相邻两个频道之间的等待时间（秒）
+    #[serde(default = "default_health_delay")]
+    pub delay: u64,
+
+    /// 播放列表中隐藏检测失败的频道
+    #[serde(default)]
+    pub hide_dead: bool,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: default_health_interval(),
+            timeout: default_health_timeout(),
+            delay: default_health_delay(),
+            hide_dead: false,
+        }
+    }
+}
+
+fn default_health_interval() -> u64 {
+    1800
+}
+
+fn default_health_timeout() -> u64 {
+    3
+}
+
+fn default_health_delay() -> u64 {
+    2
+}
+
+/// 录制配置
+#[derive(Debug, Deserialize, Clone)]
+pub struct DvrConfig {
+    /// 是否启用录制
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// 录制文件保存目录
+    #[serde(default = "default_dvr_dir")]
+    pub dir: String,
+
+    /// 节目开始前提前录制的时间（秒）
+    #[serde(default = "default_dvr_padding_before")]
+    pub padding_before: i64,
+
+    /// 节目结束后延后录制的时间（秒）
+    #[serde(default = "default_dvr_padding_after")]
+    pub padding_after: i64,
+
+    /// 同时录制的最大数量，超过视为冲突，0 表示不限制
+    #[serde(default = "default_dvr_max_concurrent")]
+    pub max_concurrent: usize,
+
+    /// 录制文件保留天数，0 表示不清理
+    #[serde(default = "default_dvr_retention_days")]
+    pub retention_days: i64,
+
+    /// 按节目名自动录制的规则
+    #[serde(default)]
+    pub rules: Vec<RecordingRule>,
+}
+
+impl Default for DvrConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: default_dvr_dir(),
+            padding_before: default_dvr_padding_before(),
+            padding_after: default_dvr_padding_after(),
+            max_concurrent: default_dvr_max_concurrent(),
+            retention_days: default_dvr_retention_days(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// 自动录制规则：节目名匹配 pattern（正则）时录制
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RecordingRule {
+    #[serde(default)]
+    pub id: u64,
+    /// 频道名称或 ID，不填表示所有频道
+    pub channel: Option<String>,
+    pub pattern: String,
+}
+
+fn default_dvr_dir() -> String {
+    "./recordings".to_string()
+}
+
+fn default_dvr_padding_before() -> i64 {
+    60
+}
+
+fn default_dvr_padding_after() -> i64 {
+    180
+}
+
+fn default_dvr_max_concurrent() -> usize {
+    2
+}
+
+fn default_dvr_retention_days() -> i64 {
+    7
+}
+
+/// 本地时移配置：把频道持续录制到磁盘上的环形缓冲区
+#[derive(Debug, Deserialize, Clone)]
+pub struct TimeshiftConfig {
+    /// 开启时移的频道（名称或 ID），为空表示不启用
+    #[serde(default)]
+    pub channels: Vec<String>,
+
+    /// 缓冲文件保存目录
+    #[serde(default = "default_timeshift_dir")]
+    pub dir: String,
+
+    /// 缓冲时长（小时）
+    #[serde(default = "default_timeshift_hours")]
+    pub hours: u64,
+
+    /// 每个分块的目标时长（秒），在关键帧处切分
+    #[serde(default = "default_timeshift_chunk_duration")]
+    pub chunk_duration: u64,
+}
+
+impl Default for TimeshiftConfig {
+    fn default() -> Self {
+        Self {
+            channels: Vec::new(),
+            dir: default_timeshift_dir(),
+            hours: default_timeshift_hours(),
+            chunk_duration: default_timeshift_chunk_duration(),
+        }
+    }
+}
+
+impl TimeshiftConfig {
+    /// 频道是否开启了时移
+    pub fn includes(&self, channel_id: u64, channel_name: &str) -> bool {
+        self.channels
+            .iter()
+            .any(|c| c == channel_name || *c == channel_id.to_string())
+    }
+}
+
+fn default_timeshift_dir() -> String {
+    "./timeshift".to_string()
+}
+
+fn default_timeshift_hours() -> u64 {
+    3
+}
+
+fn default_timeshift_chunk_duration() -> u64 {
+    10
+}
+
+/// 观看会话配置
+#[derive(Debug, Deserialize, Clone)]
+pub struct SessionConfig {
+    /// 内存中保留的观看记录条数
+    #[serde(default = "default_session_history_size")]
+    pub history_size: usize,
+
+    /// 观看记录追加写入的文件（每行一条 JSON），为空表示不写文件
+    #[serde(default)]
+    pub history_file: String,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            history_size: default_session_history_size(),
+            history_file: String::new(),
+        }
+    }
+}
+
+fn default_session_history_size() -> usize {
+    1000
+}
+
+/// 组播转单播：把频道的组播转发到指定的 host:port
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ForwardTarget {
+    /// 频道名称或 ID
+    pub channel: String,
+    /// 目标地址 host:port
+    pub target: String,
+    /// 是否封装成 RTP，否则直接发送 UDP（每包 7 个 TS 包）
+    #[serde(default)]
+    pub rtp: bool,
+    /// 发送使用的网卡，不填时使用 iptv.interface
+    pub interface: Option<String>,
+}
+
+/// /play/{channel_id} 播放配置
+#[derive(Debug, Deserialize, Clone)]
+pub struct PlayConfig {
+    /// 转发数据还是重定向到当前的上游地址
+    #[serde(default)]
+    pub mode: PlayMode,
+
+    /// 组播中断时是否切换到 RTSP 直播地址
+    #[serde(default = "default_true")]
+    pub fallback: bool,
+
+    /// 组播多久没有数据视为中断（秒）
+    #[serde(default = "default_play_stall_timeout")]
+    pub stall_timeout: u64,
+
+    /// 组播恢复后持续有数据多久切回组播（秒）
+    #[serde(default = "default_play_recover_after")]
+    pub recover_after: u64,
+}
+
+impl Default for PlayConfig {
+    fn default() -> Self {
+        Self {
+            mode: PlayMode::default(),
+            fallback: true,
+            stall_timeout: default_play_stall_timeout(),
+            recover_after: default_play_recover_after(),
+        }
+    }
+}
+
+/// /play 的处理方式
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayMode {
+    /// 由本服务转发，组播中断时可切换到 RTSP
+    #[default]
+    Relay,
+    /// 302 重定向到当前的组播（经 udp_proxy_uri）或 RTSP 地址
+    Redirect,
+}
+
+/// 回看配置（/catchup/{channel_id} 及播放列表的 catchup 标签）
+#[derive(Debug, Deserialize, Clone)]
+pub struct CatchupConfig {
+    /// 播放列表默认使用的回看方式
+    #[serde(default)]
+    pub mode: CatchupMode,
+
+    /// 播放器传入 yyyyMMddHHmmss 格式时间时使用的时区（与 UTC 相差的小时数）
+    #[serde(default = "default_catchup_timezone")]
+    pub timezone: i32,
+
+    /// 播放器配置：名称 -> 回看方式，通过 /playlist?profile=名称 选择
+    #[serde(default)]
+    pub profiles: HashMap<String, CatchupMode>,
+}
+
+impl Default for CatchupConfig {
+    fn default() -> Self {
+        Self {
+            mode: CatchupMode::default(),
+            timezone: default_catchup_timezone(),
+            profiles: HashMap::new(),
+        }
+    }
+}
+
+/// 播放列表中的回看方式
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CatchupMode {
+    /// catchup="default"，回看地址直接带 playseek 模板
+    #[default]
+    Default,
+    /// catchup="append"，播放器在直播地址后追加 ?utc={utc}&lutc={lutc}
+    Append,
+    /// catchup="shift"，播放器自动在直播地址后追加 utc/lutc 参数
+    Shift,
+}
+
+fn default_catchup_timezone() -> i32 {
+    8
+}
+
+/// 地址签名配置，对外开放本服务时使用
+#[derive(Debug, Deserialize, Clone)]
+pub struct SignConfig {
+    /// 签名密钥，为空时不签名也不检查
+    #[serde(default)]
+    pub key: String,
+
+    /// 签名有效期（秒）
+    #[serde(default = "default_sign_expire")]
+    pub expire: u64,
+
+    /// 签名绑定获取播放列表的客户端 IP
+    #[serde(default)]
+    pub bind_client: bool,
+}
+
+impl Default for SignConfig {
+    fn default() -> Self {
+        Self {
+            key: String::new(),
+            expire: default_sign_expire(),
+            bind_client: false,
+        }
+    }
+}
+
+fn default_sign_expire() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+fn default_play_stall_timeout() -> u64 {
+    3
+}
+
+fn default_play_recover_after() -> u64 {
+    10
+}
 
 impl YamlConfig {
     /// 从文件加载配置
@@ -79,4 +775,62 @@ impl YamlConfig {
         let config: YamlConfig = serde_yaml::from_str(&config_content)?;
         Ok(config)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts(yaml: &str) -> Result<IptvAccounts, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    #[test]
+    fn single_or_list() {
+        let one = accounts("user: a\npasswd: p\n").unwrap();
+        assert_eq!(one.len(), 1);
+        assert_eq!(one[0].session_ttl, default_iptv_session_ttl());
+
+        let many = accounts("- user: a\n  passwd: p\n- user: b\n  passwd: p\n").unwrap();
+        assert_eq!(many.len(), 2);
+        assert_eq!(many.account(5).user, "a");
+        assert_eq!(many[1].index, 1);
+
+        assert!(accounts("[]").is_err());
+    }
+
+    #[test]
+    fn field_errors() {
+        let err = accounts("user: a\npasswd: p\nsession_ttl: abc\n").unwrap_err();
+        assert!(err.to_string().contains("session_ttl"), "{}", err);
+        let err = accounts("- user: a\n  passwd: p\n- passwd: p\n").unwrap_err();
+        assert!(err.to_string().contains("missing field `user`"), "{}", err);
+        let err = accounts("abc").unwrap_err();
+        assert!(err.to_string().contains("一个账号或账号列表"), "{}", err);
+    }
+
+    #[test]
+    fn session_ttl_lower_bound() {
+        assert!(accounts("user: a\npasswd: p\nsession_ttl: 0\n").is_err());
+        assert!(accounts("user: a\npasswd: p\nsession_ttl: 59\n").is_err());
+        assert!(accounts("user: a\npasswd: p\nsession_ttl: 60\n").is_ok());
+        assert!(
+            accounts("- user: a\n  passwd: p\n- user: b\n  passwd: p\n  session_ttl: 1\n").is_err()
+        );
+    }
+
+    #[test]
+    fn duplicate_accounts() {
+        assert!(accounts("- user: a\n  passwd: p\n- user: a\n  passwd: q\n").is_err());
+        assert!(accounts(
+            "- user: a\n  passwd: p\n  interface: eth1\n- user: a\n  passwd: p\n  interface: eth1\n"
+        )
+        .is_err());
+
+        let accounts = accounts(
+            "- user: a\n  passwd: p\n  interface: eth1\n- user: a\n  passwd: p\n  interface: eth2\n",
+        )
+        .unwrap();
+        assert_eq!(accounts.interface(1), Some("eth2"));
+        assert_eq!(accounts[1].index, 1);
+    }
+}
@@ -0,0 +1,973 @@
+// dvr.rs
+// 按 EPG 录制：按节目或节目名规则调度，把组播（或 RTSP 回看）保存为本地 TS 文件
+use actix_web::{
+    delete, get, post,
+    web::{Data, Json, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use anyhow::{anyhow, Result};
+use chrono::{TimeZone, Utc};
+use log::{debug, error, info, warn};
+use once_cell::sync::Lazy;
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::catchup::tz_from_hours;
+use crate::config::{RecordingRule, YamlConfig};
+use crate::iptv::{find_channel, get_channels_epg, Channel};
+use crate::multicast::{parse_group_addr, subscribe, Subscription};
+use crate::rtsp::RtspPlayer;
+use crate::sign::check_local;
+
+/// 调度检查间隔
+const TICK: Duration = Duration::from_secs(10);
+/// EPG 刷新间隔
+const EPG_REFRESH: Duration = Duration::from_secs(3600);
+/// 录制进度写回状态的间隔
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+/// 节目开始超过该时间（毫秒）才改用 RTSP 回看录制
+const CATCHUP_THRESHOLD: i64 = 60_000;
+
+// 录制计划和接口添加的规则
+static STATE: Lazy<Mutex<DvrState>> = Lazy::new(|| Mutex::new(DvrState::default()));
+
+// 正在录制的任务，用于取消
+static CANCEL: Lazy<Mutex<HashMap<u64, Arc<AtomicBool>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// 带 EPG 的频道列表，用于规则匹配
+static EPG: Lazy<RwLock<Vec<Channel>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingState {
+    Scheduled,
+    Recording,
+    Completed,
+    Failed,
+    Conflict,
+    Cancelled,
+}
+
+/// 一个录制任务，时间为毫秒时间戳（节目本身的起止，不含提前/延后）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub id: u64,
+    pub channel_id: u64,
+    pub channel_name: String,
+    pub title: String,
+    pub start: i64,
+    pub stop: i64,
+    pub state: RecordingState,
+    pub rule_id: Option<u64>,
+    pub file: Option<String>,
+    pub bytes: u64,
+    pub error: Option<String>,
+}
+
+impl Recording {
+    /// 加上提前/延后后的实际录制时间
+    fn window(&self, config: &YamlConfig) -> (i64, i64) {
+        (
+            self.start - config.dvr.padding_before * 1000,
+            self.stop + config.dvr.padding_after * 1000,
+        )
+    }
+
+    fn active(&self) -> bool {
+        matches!(
+            self.state,
+            RecordingState::Scheduled | RecordingState::Recording
+        )
+    }
+
+    /// 还没有开始的录制，包括冲突的
+    fn pending(&self) -> bool {
+        matches!(
+            self.state,
+            RecordingState::Scheduled | RecordingState::Conflict
+        )
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct DvrState {
+    next_id: u64,
+    recordings: Vec<Recording>,
+    rules: Vec<RecordingRule>,
+    /// 配置文件中的规则，启动时从 next_id 分配 ID，不保存
+    #[serde(skip)]
+    config_rules: Vec<RecordingRule>,
+}
+
+impl DvrState {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// 载入配置文件中的规则，和接口添加的规则使用同一个 ID 计数
+    fn init_config_rules(&mut self, config: &YamlConfig) {
+        self.config_rules = config.dvr.rules.clone();
+        for i in 0..self.config_rules.len() {
+            self.config_rules[i].id = self.next_id();
+        }
+    }
+
+    /// 配置文件和接口添加的所有规则
+    fn all_rules(&self) -> Vec<RecordingRule> {
+        self.config_rules
+            .iter()
+            .chain(self.rules.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// 与 window 重叠、仍在进行中的录制是否已经达到上限，max_concurrent 为 0 时不限制
+    fn has_conflict(&self, config: &YamlConfig, window: (i64, i64)) -> bool {
+        if config.dvr.max_concurrent == 0 {
+            return false;
+        }
+        let overlapping = self
+            .recordings
+            .iter()
+            .filter(|r| r.active())
+            .filter(|r| {
+                let (start, stop) = r.window(config);
+                start < window.1 && window.0 < stop
+            })
+            .count();
+        overlapping >= config.dvr.max_concurrent
+    }
+
+    /// 录制被删除或结束后，按开始时间重新检查冲突的录制，返回是否有录制恢复
+    fn resolve_conflicts(&mut self, config: &YamlConfig) -> bool {
+        let mut conflicts: Vec<(i64, u64)> = self
+            .recordings
+            .iter()
+            .filter(|r| r.state == RecordingState::Conflict)
+            .map(|r| (r.start, r.id))
+            .collect();
+        conflicts.sort();
+
+        let mut changed = false;
+        for (_, id) in conflicts {
+            let Some(index) = self.recordings.iter().position(|r| r.id == id) else {
+                continue;
+            };
+            if !self.has_conflict(config, self.recordings[index].window(config)) {
+                let recording = &mut self.recordings[index];
+                info!(
+                    "录制不再冲突: {} {}",
+                    recording.channel_name, recording.title
+                );
+                recording.state = RecordingState::Scheduled;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// 删除录制计划
+    ///
+    /// 规则添加、还没有开始的录制只标记为取消，否则下次按规则调度时会被重新添加。
+    fn remove(&mut self, id: u64) -> Option<Recording> {
+        let index = self.recordings.iter().position(|r| r.id == id)?;
+        let recording = &mut self.recordings[index];
+        if recording.rule_id.is_some() && recording.pending() {
+            recording.state = RecordingState::Cancelled;
+            return Some(recording.clone());
+        }
+        Some(self.recordings.remove(index))
+    }
+
+    /// 添加录制计划，冲突时状态为 Conflict
+    fn schedule(
+        &mut self,
+        config: &YamlConfig,
+        channel: &Channel,
+        title: String,
+        start: i64,
+        stop: i64,
+        rule_id: Option<u64>,
+    ) -> Result<Recording> {
+        if stop <= start {
+            return Err(anyhow!("结束时间必须晚于开始时间"));
+        }
+        if self.recordings.iter().any(|r| {
+            r.channel_id == channel.id && r.start == start && r.state != RecordingState::Cancelled
+        }) {
+            return Err(anyhow!("频道 {} 在该时间已有录制", channel.name));
+        }
+
+        let mut recording = Recording {
+            id: self.next_id(),
+            channel_id: channel.id,
+            channel_name: channel.name.clone(),
+            title,
+            start,
+            stop,
+            state: RecordingState::Scheduled,
+            rule_id,
+            file: None,
+            bytes: 0,
+            error: None,
+        };
+        if self.has_conflict(config, recording.window(config)) {
+            warn!("录制冲突: {} {}", recording.channel_name, recording.title);
+            recording.state = RecordingState::Conflict;
+        }
+        info!(
+            "添加录制: {} {} ({:?})",
+            recording.channel_name, recording.title, recording.state
+        );
+        self.recordings.push(recording.clone());
+        Ok(recording)
+    }
+}
+
+fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+fn state_file(config: &YamlConfig) -> PathBuf {
+    PathBuf::from(&config.dvr.dir).join("recordings.json")
+}
+
+/// 读取保存的录制计划
+fn load(config: &YamlConfig) {
+    let state = std::fs::read_to_string(state_file(config))
+        .ok()
+        .and_then(|s| serde_json::from_str::<DvrState>(&s).ok());
+    if let (Some(mut state), Ok(mut current)) = (state, STATE.lock()) {
+        // 重启前正在录制的任务已经中断
+        for r in state.recordings.iter_mut() {
+            if r.state == RecordingState::Recording {
+                r.state = RecordingState::Failed;
+                r.error = Some("服务重启，录制中断".to_string());
+            }
+        }
+        info!("载入 {} 个录制计划", state.recordings.len());
+        *current = state;
+    }
+}
+
+/// 保存录制计划
+fn save(config: &YamlConfig) {
+    let json = match STATE.lock() {
+        Ok(state) => serde_json::to_string_pretty(&*state),
+        Err(_) => return,
+    };
+    match json {
+        Ok(json) => {
+            if let Err(e) = std::fs::write(state_file(config), json) {
+                error!("保存录制计划失败: {}", e);
+            }
+        }
+        Err(e) => error!("序列化录制计划失败: {}", e),
+    }
+}
+
+fn update_recording(id: u64, f: impl FnOnce(&mut Recording)) {
+    if let Ok(mut state) = STATE.lock() {
+        if let Some(r) = state.recordings.iter_mut().find(|r| r.id == id) {
+            f(r);
+        }
+    }
+}
+
+fn rule_matches_channel(rule: &RecordingRule, channel: &Channel) -> bool {
+    match &rule.channel {
+        Some(c) => *c == channel.name || *c == channel.id.to_string(),
+        None => true,
+    }
+}
+
+impl DvrState {
+    /// 按规则从 EPG 中添加 now 之后的节目，返回是否添加了录制
+    fn apply_rules(
+        &mut self,
+        config: &YamlConfig,
+        rules: &[RecordingRule],
+        epg: &[Channel],
+        now: i64,
+    ) -> bool {
+        let mut changed = false;
+        for rule in rules.iter() {
+            let pattern = match Regex::new(&rule.pattern) {
+                Ok(p) => p,
+                Err(e) => {
+                    warn!("录制规则 {} 无效: {}", rule.pattern, e);
+                    continue;
+                }
+            };
+            for channel in epg.iter().filter(|c| rule_matches_channel(rule, c)) {
+                for program in channel.epg.iter() {
+                    if program.start <= now || !pattern.is_match(&program.title) {
+                        continue;
+                    }
+                    // 包括已取消的录制，用户取消的单集不会被重新添加
+                    let exists = self
+                        .recordings
+                        .iter()
+                        .any(|r| r.channel_id == channel.id && r.start == program.start);
+                    if !exists {
+                        changed |= self
+                            .schedule(
+                                config,
+                                channel,
+                                program.title.clone(),
+                                program.start,
+                                program.stop,
+                                Some(rule.id),
+                            )
+                            .is_ok();
+                    }
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// 按规则从 EPG 中添加录制计划，返回是否添加了录制
+fn apply_rules(config: &YamlConfig) -> bool {
+    let epg = match EPG.read() {
+        Ok(epg) => epg,
+        Err(_) => return false,
+    };
+    let mut state = match STATE.lock() {
+        Ok(state) => state,
+        Err(_) => return false,
+    };
+    let rules = state.all_rules();
+    state.apply_rules(config, &rules, &epg, now_millis())
+}
+
+/// 重新检查冲突的录制
+fn resolve_conflicts(config: &YamlConfig) -> bool {
+    STATE
+        .lock()
+        .map(|mut state| state.resolve_conflicts(config))
+        .unwrap_or(false)
+}
+
+/// 删除过期的录制文件，返回是否删除了录制
+fn cleanup(config: &YamlConfig) -> bool {
+    if config.dvr.retention_days <= 0 {
+        return false;
+    }
+    let expire = now_millis() - config.dvr.retention_days * 86_400_000;
+    let Ok(mut state) = STATE.lock() else {
+        return false;
+    };
+    let before = state.recordings.len();
+    state.recordings.retain(|r| {
+        if r.active() || r.stop > expire {
+            return true;
+        }
+        if let Some(file) = &r.file {
+            match std::fs::remove_file(file) {
+                Ok(()) => info!("删除过期录制 {}", file),
+                Err(e) => debug!("删除 {} 失败: {}", file, e),
+            }
+        }
+        false
+    });
+    state.recordings.len() != before
+}
+
+/// 录制文件路径: {dir}/{频道}/{开始时间}_{节目}.ts
+fn recording_path(config: &YamlConfig, recording: &Recording) -> PathBuf {
+    let sanitize = |s: &str| -> String {
+        s.chars()
+            .map(|c| {
+                if "/\\:*?\"<>|".contains(c) || c.is_whitespace() {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect()
+    };
+    // 文件名使用 catchup.timezone 时区的时间
+    let start = tz_from_hours(config.catchup.timezone)
+        .and_then(|tz| tz.timestamp_millis_opt(recording.start).single())
+        .map(|t| t.format("%Y%m%d_%H%M").to_string())
+        .unwrap_or_else(|| recording.start.to_string());
+
+    PathBuf::from(&config.dvr.dir)
+        .join(sanitize(&recording.channel_name))
+        .join(format!("{}_{}.ts", start, sanitize(&recording.title)))
+}
+
+/// 执行一个录制任务
+async fn record(config: YamlConfig, recording: Recording, cancel: Arc<AtomicBool>) {
+    info!("开始录制: {} {}", recording.channel_name, recording.title);
+    let result = record_to_file(&config, &recording, &cancel).await;
+
+    update_recording(recording.id, |r| match result {
+        Ok(()) if cancel.load(Ordering::Relaxed) => r.state = RecordingState::Cancelled,
+        Ok(()) => r.state = RecordingState::Completed,
+        Err(e) => {
+            error!("录制 {} {} 失败: {}", r.channel_name, r.title, e);
+            r.state = RecordingState::Failed;
+            r.error = Some(e.to_string());
+        }
+    });
+    if let Ok(mut tasks) = CANCEL.lock() {
+        tasks.remove(&recording.id);
+    }
+    save(&config);
+    info!("录制结束: {} {}", recording.channel_name, recording.title);
+}
+
+async fn record_to_file(
+    config: &YamlConfig,
+    recording: &Recording,
+    cancel: &AtomicBool,
+) -> Result<()> {
+    let channel = find_channel(&config.iptv, recording.channel_id).await?;
+    let path = recording_path(config, recording);
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut file = tokio::fs::File::create(&path).await?;
+    let file_name = path.to_string_lossy().into_owned();
+    update_recording(recording.id, |r| r.file = Some(file_name));
+
+    let (begin, end) = recording.window(config);
+    let timeout = Duration::from_secs(config.relay.timeout);
+    let mut bytes = 0u64;
+    let mut last_progress = Instant::now();
+
+    // 节目已经开始一段时间，用 RTSP 回看从头录制
+    if begin < now_millis() - CATCHUP_THRESHOLD && !channel.rtsp.is_empty() {
+        let mut player = RtspPlayer::open(
+            &channel.rtsp,
+            Some((begin, Some(end))),
+            config.iptv.interface(channel.account),
+        )
+        .await?;
+        let max_duration =
+            Duration::from_millis((end - begin).max(0) as u64) + Duration::from_secs(300);
+        let started = Instant::now();
+
+        while !cancel.load(Ordering::Relaxed) && started.elapsed() < max_duration {
+            let chunk = match player.read(timeout).await {
+                Ok(chunk) => chunk,
+                // 回看结束时服务器会关闭连接
+                Err(e) => {
+                    debug!("RTSP 回看结束: {}", e);
+                    break;
+                }
+            };
+            file.write_all(&chunk).await?;
+            bytes += chunk.len() as u64;
+            if last_progress.elapsed() > PROGRESS_INTERVAL {
+                update_recording(recording.id, |r| r.bytes = bytes);
+                last_progress = Instant::now();
+            }
+        }
+    } else {
+        let addr = parse_group_addr(&channel.igmp)?;
+        let Subscription {
+            cached,
+            mut receiver,
+            ..
+        } = subscribe(addr, config.iptv.interface(channel.account), &config.relay)?;
+        for chunk in cached {
+            file.write_all(&chunk).await?;
+            bytes += chunk.len() as u64;
+        }
+
+        while !cancel.load(Ordering::Relaxed) && now_millis() < end {
+            let chunk = match tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await {
+                Ok(Ok(chunk)) => chunk,
+                Ok(Err(RecvError::Lagged(skipped))) => {
+                    warn!("录制 {} 跳过 {} 个包", recording.title, skipped);
+                    continue;
+                }
+                Ok(Err(RecvError::Closed)) => return Err(anyhow!("组播已关闭")),
+                Err(_) => continue,
+            };
+            file.write_all(&chunk).await?;
+            bytes += chunk.len() as u64;
+            if last_progress.elapsed() > PROGRESS_INTERVAL {
+                update_recording(recording.id, |r| r.bytes = bytes);
+                last_progress = Instant::now();
+            }
+        }
+    }
+
+    file.flush().await?;
+    update_recording(recording.id, |r| r.bytes = bytes);
+    if bytes == 0 {
+        return Err(anyhow!("没有收到数据"));
+    }
+    Ok(())
+}
+
+/// 启动到时间的录制，标记错过的录制
+fn start_due(config: &YamlConfig) -> bool {
+    let now = now_millis();
+    let mut due = Vec::new();
+    let mut changed = false;
+
+    if let Ok(mut state) = STATE.lock() {
+        for r in state.recordings.iter_mut() {
+            let (begin, end) = r.window(config);
+            match r.state {
+                RecordingState::Scheduled if now >= end => {
+                    r.state = RecordingState::Failed;
+                    r.error = Some("错过录制时间".to_string());
+                    changed = true;
+                }
+                RecordingState::Scheduled if now >= begin => {
+                    r.state = RecordingState::Recording;
+                    due.push(r.clone());
+                    changed = true;
+                }
+                RecordingState::Conflict if now >= end => {
+                    r.state = RecordingState::Failed;
+                    r.error = Some("与其他录制冲突".to_string());
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for recording in due {
+        let cancel = Arc::new(AtomicBool::new(false));
+        if let Ok(mut tasks) = CANCEL.lock() {
+            tasks.insert(recording.id, cancel.clone());
+        }
+        tokio::spawn(record(config.clone(), recording, cancel));
+    }
+    changed
+}
+
+/// 后台录制调度
+pub(crate) async fn dvr_loop(config: YamlConfig) {
+    if !config.dvr.enabled {
+        return;
+    }
+    if let Err(e) = std::fs::create_dir_all(&config.dvr.dir) {
+        error!("创建录制目录 {} 失败: {}", config.dvr.dir, e);
+        return;
+    }
+    load(&config);
+    if let Ok(mut state) = STATE.lock() {
+        state.init_config_rules(&config);
+    }
+
+    let mut epg_updated: Option<Instant> = None;
+    let mut cleaned: Option<Instant> = None;
+    loop {
+        if epg_updated.is_none_or(|t| t.elapsed() > EPG_REFRESH) {
+            match get_channels_epg(&config.iptv).await {
+                Ok(channels) => {
+                    if let Ok(mut epg) = EPG.write() {
+                        *epg = channels;
+                    }
+                    epg_updated = Some(Instant::now());
+                }
+                Err(e) => warn!("录制调度获取 EPG 失败: {}", e),
+            }
+        }
+        let mut changed = false;
+        if cleaned.is_none_or(|t| t.elapsed() > EPG_REFRESH) {
+            changed |= cleanup(&config);
+            cleaned = Some(Instant::now());
+        }
+
+        changed |= apply_rules(&config);
+        // 录制结束或被删除后，冲突的录制可能可以开始了
+        changed |= resolve_conflicts(&config);
+        changed |= start_due(&config);
+        // 只在录制计划有变化时写文件，避免反复写路由器的闪存
+        if changed {
+            save(&config);
+        }
+        tokio::time::sleep(TICK).await;
+    }
+}
+
+fn disabled() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(serde_json::json!({ "error": "录制未启用" }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewRecording {
+    pub channel_id: u64,
+    /// 节目开始时间（毫秒）
+    pub start: i64,
+    /// 节目结束时间（毫秒）
+    pub stop: i64,
+    pub title: Option<String>,
+}
+
+#[get("/api/recordings")]
+pub async fn list_recordings(config: Data<YamlConfig>, req: HttpRequest) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    let recordings = STATE
+        .lock()
+        .map(|s| s.recordings.clone())
+        .unwrap_or_default();
+    HttpResponse::Ok().json(recordings)
+}
+
+#[post("/api/recordings")]
+pub async fn add_recording(
+    config: Data<YamlConfig>,
+    body: Json<NewRecording>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    if !config.dvr.enabled {
+        return disabled();
+    }
+    let channel = match find_channel(&config.iptv, body.channel_id).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            return HttpResponse::NotFound().json(serde_json::json!({ "error": e.to_string() }))
+        }
+    };
+
+    // 没有给出节目名时从 EPG 中查找
+    let title = body.title.clone().unwrap_or_else(|| {
+        EPG.read()
+            .ok()
+            .and_then(|epg| {
+                epg.iter()
+                    .find(|c| c.id == body.channel_id)
+                    .and_then(|c| c.epg.iter().find(|p| p.start == body.start))
+                    .map(|p| p.title.clone())
+            })
+            .unwrap_or_else(|| channel.name.clone())
+    });
+
+    let result = match STATE.lock() {
+        Ok(mut state) => state.schedule(&config, &channel, title, body.start, body.stop, None),
+        Err(_) => Err(anyhow!("录制列表锁异常")),
+    };
+    match result {
+        Ok(recording) if recording.state == RecordingState::Conflict => {
+            save(&config);
+            HttpResponse::Conflict().json(recording)
+        }
+        Ok(recording) => {
+            save(&config);
+            HttpResponse::Created().json(recording)
+        }
+        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+#[delete("/api/recordings/{id}")]
+pub async fn delete_recording(
+    config: Data<YamlConfig>,
+    path: Path<u64>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    let id = path.into_inner();
+
+    // 正在录制的先停止，保留已录制的文件
+    if let Some(cancel) = CANCEL.lock().ok().and_then(|t| t.get(&id).cloned()) {
+        cancel.store(true, Ordering::Relaxed);
+        return HttpResponse::Accepted()
+            .json(serde_json::json!({ "id": id, "state": "cancelled" }));
+    }
+
+    let removed = match STATE.lock() {
+        Ok(mut state) => {
+            let removed = state.remove(id);
+            state.resolve_conflicts(&config);
+            removed
+        }
+        Err(_) => None,
+    };
+    match removed {
+        Some(recording) => {
+            if let Some(file) = &recording.file {
+                let _ = std::fs::remove_file(file);
+            }
+            save(&config);
+            HttpResponse::Ok().json(recording)
+        }
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "录制不存在" })),
+    }
+}
+
+#[get("/api/recordings/rules")]
+pub async fn list_rules(config: Data<YamlConfig>, req: HttpRequest) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    let rules = STATE
+        .lock()
+        .map(|state| state.all_rules())
+        .unwrap_or_default();
+    HttpResponse::Ok().json(rules)
+}
+
+#[post("/api/recordings/rules")]
+pub async fn add_rule(
+    config: Data<YamlConfig>,
+    body: Json<RecordingRule>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    if !config.dvr.enabled {
+        return disabled();
+    }
+    if let Err(e) = Regex::new(&body.pattern) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": e.to_string() }));
+    }
+
+    let mut rule = body.into_inner();
+    if let Ok(mut state) = STATE.lock() {
+        rule.id = state.next_id();
+        state.rules.push(rule.clone());
+    }
+    apply_rules(&config);
+    save(&config);
+    HttpResponse::Created().json(rule)
+}
+
+#[delete("/api/recordings/rules/{id}")]
+pub async fn delete_rule(
+    config: Data<YamlConfig>,
+    path: Path<u64>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    let id = path.into_inner();
+    let removed = match STATE.lock() {
+        Ok(state) if state.config_rules.iter().any(|r| r.id == id) => {
+            return HttpResponse::BadRequest()
+                .json(serde_json::json!({ "error": "配置文件中的规则不能通过接口删除" }));
+        }
+        Ok(mut state) => {
+            let before = state.rules.len();
+            state.rules.retain(|r| r.id != id);
+            // 同时取消该规则尚未开始的录制
+            state
+                .recordings
+                .retain(|r| !(r.rule_id == Some(id) && r.pending()));
+            state.resolve_conflicts(&config);
+            state.rules.len() != before
+        }
+        Err(_) => false,
+    };
+    if !removed {
+        return HttpResponse::NotFound().json(serde_json::json!({ "error": "规则不存在" }));
+    }
+    save(&config);
+    HttpResponse::Ok().json(serde_json::json!({ "id": id }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iptv::Program;
+
+    const HOUR: i64 = 3_600_000;
+
+    fn config() -> YamlConfig {
+        config_with("max_concurrent: 1")
+    }
+
+    fn config_with(dvr: &str) -> YamlConfig {
+        serde_yaml::from_str(&format!(
+            r#"
+server: {{}}
+iptv:
+  user: "user"
+  passwd: "passwd"
+m3u8: {{}}
+dvr:
+  enabled: true
+  padding_before: 0
+  padding_after: 0
+  {}
+"#,
+            dvr
+        ))
+        .unwrap()
+    }
+
+    fn manual(state: &mut DvrState, config: &YamlConfig, channel_id: u64) -> Recording {
+        state
+            .schedule(
+                config,
+                &channel(channel_id, &[]),
+                "手动".to_string(),
+                HOUR,
+                2 * HOUR,
+                None,
+            )
+            .unwrap()
+    }
+
+    fn channel(id: u64, programs: &[(i64, &str)]) -> Channel {
+        Channel {
+            id,
+            user_channel_id: String::new(),
+            name: format!("频道{}", id),
+            rtsp: String::new(),
+            igmp: String::new(),
+            epg: programs
+                .iter()
+                .map(|(start, title)| Program {
+                    start: *start,
+                    stop: start + HOUR,
+                    title: title.to_string(),
+                    desc: String::new(),
+                })
+                .collect(),
+            account: 0,
+        }
+    }
+
+    fn rule(id: u64, pattern: &str) -> RecordingRule {
+        RecordingRule {
+            id,
+            channel: None,
+            pattern: pattern.to_string(),
+        }
+    }
+
+    #[test]
+    fn rules_schedule_matching_programs() {
+        let config = config();
+        let epg = [channel(1, &[(HOUR, "新闻联播"), (2 * HOUR, "天气预报")])];
+        let mut state = DvrState::default();
+
+        assert!(state.apply_rules(&config, &[rule(100, "新闻")], &epg, 0));
+        assert_eq!(state.recordings.len(), 1);
+        assert_eq!(state.recordings[0].rule_id, Some(100));
+        // 已经添加过的节目不重复添加
+        assert!(!state.apply_rules(&config, &[rule(100, "新闻")], &epg, 0));
+        // 已经开始的节目不添加
+        assert!(!state.apply_rules(&config, &[rule(101, "天气")], &epg, 2 * HOUR));
+    }
+
+    #[test]
+    fn cancelled_episode_not_rescheduled() {
+        let config = config();
+        let epg = [channel(1, &[(HOUR, "新闻联播")])];
+        let rules = [rule(100, "新闻")];
+        let mut state = DvrState::default();
+        state.apply_rules(&config, &rules, &epg, 0);
+        let id = state.recordings[0].id;
+
+        let removed = state.remove(id).unwrap();
+        assert_eq!(removed.state, RecordingState::Cancelled);
+        assert_eq!(state.recordings.len(), 1);
+        assert!(!state.apply_rules(&config, &rules, &epg, 0));
+        assert_eq!(state.recordings[0].state, RecordingState::Cancelled);
+    }
+
+    #[test]
+    fn manual_recording_removed() {
+        let config = config();
+        let mut state = DvrState::default();
+        let recording = state
+            .schedule(
+                &config,
+                &channel(1, &[]),
+                "手动".to_string(),
+                HOUR,
+                2 * HOUR,
+                None,
+            )
+            .unwrap();
+
+        assert!(state.remove(recording.id).is_some());
+        assert!(state.recordings.is_empty());
+        assert!(state.remove(recording.id).is_none());
+    }
+
+    #[test]
+    fn conflict_resolved_after_delete() {
+        let config = config();
+        let mut state = DvrState::default();
+        let first = manual(&mut state, &config, 1);
+        let second = manual(&mut state, &config, 2);
+        let third = manual(&mut state, &config, 3);
+        assert_eq!(first.state, RecordingState::Scheduled);
+        assert_eq!(second.state, RecordingState::Conflict);
+        assert_eq!(third.state, RecordingState::Conflict);
+        assert!(!state.resolve_conflicts(&config));
+
+        state.remove(first.id);
+        assert!(state.resolve_conflicts(&config));
+        let states: Vec<_> = state.recordings.iter().map(|r| r.state).collect();
+        // 只恢复一个，另一个仍然冲突
+        assert_eq!(
+            states,
+            [RecordingState::Scheduled, RecordingState::Conflict]
+        );
+    }
+
+    #[test]
+    fn conflict_resolved_after_finish() {
+        let config = config();
+        let mut state = DvrState::default();
+        let first = manual(&mut state, &config, 1);
+        manual(&mut state, &config, 2);
+
+        state.recordings[0].state = RecordingState::Completed;
+        assert!(state.resolve_conflicts(&config));
+        assert_eq!(state.recordings[1].state, RecordingState::Scheduled);
+        assert_eq!(state.recordings[0].id, first.id);
+    }
+
+    #[test]
+    fn zero_max_concurrent_is_unlimited() {
+        let config = config_with("max_concurrent: 0");
+        let mut state = DvrState::default();
+        for id in 1..=5 {
+            assert_eq!(
+                manual(&mut state, &config, id).state,
+                RecordingState::Scheduled
+            );
+        }
+    }
+
+    #[test]
+    fn config_rule_ids_from_counter() {
+        let config = config_with("rules:\n    - pattern: 新闻\n    - pattern: 天气");
+        let mut state = DvrState {
+            next_id: 10,
+            ..Default::default()
+        };
+        state.init_config_rules(&config);
+        let ids: Vec<u64> = state.all_rules().iter().map(|r| r.id).collect();
+        assert_eq!(ids, [11, 12]);
+        assert_eq!(state.next_id(), 13);
+        // 配置中的规则不会被保存
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(!json.contains("新闻"));
+    }
+}
@@ -0,0 +1,286 @@
+// forward.rs
+// 组播转单播：把频道的组播转发到指定的 host:port，供只能接收单播的机顶盒使用
+use actix_web::{
+    delete, get, post,
+    web::{Data, Json, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Notify;
+
+use crate::config::{ForwardTarget, YamlConfig};
+use crate::iptv::{get_cached_channels, Channel};
+use crate::limits::limit_response;
+use crate::multicast::{parse_group_addr, subscribe, Subscription};
+use crate::rtp::RtpPacketizer;
+use crate::sign::check_local;
+use crate::ts::{packets, TS_PACKET_SIZE};
+use crate::utils::udp_socket_with_if;
+
+/// 每个 UDP 包携带的 TS 包数
+const TS_PACKETS_PER_DATAGRAM: usize = 7;
+
+/// 启动时获取频道列表失败后的重试间隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+// forward_id -> 正在进行的转发
+static FORWARDS: Lazy<Mutex<HashMap<u64, Arc<Forward>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 一个正在进行的转发
+struct Forward {
+    id: u64,
+    channel_id: u64,
+    channel: String,
+    target: SocketAddr,
+    rtp: bool,
+    /// 开始时间（秒级时间戳）
+    start: i64,
+    packets: AtomicU64,
+    bytes: AtomicU64,
+    stop: Notify,
+}
+
+/// 转发状态，用于接口
+#[derive(Debug, Serialize)]
+pub struct ForwardStatus {
+    pub id: u64,
+    pub channel_id: u64,
+    pub channel: String,
+    pub target: String,
+    pub rtp: bool,
+    pub start: i64,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Forward {
+    fn status(&self) -> ForwardStatus {
+        ForwardStatus {
+            id: self.id,
+            channel_id: self.channel_id,
+            channel: self.channel.clone(),
+            target: self.target.to_string(),
+            rtp: self.rtp,
+            start: self.start,
+            packets: self.packets.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn matches_channel(channel: &Channel, name: &str) -> bool {
+    channel.name == name || channel.id.to_string() == name
+}
+
+/// 开始转发一个频道
+async fn start_forward(
+    config: &YamlConfig,
+    channels: &[Channel],
+    target: &ForwardTarget,
+) -> Result<ForwardStatus> {
+    let channel = channels
+        .iter()
+        .find(|c| matches_channel(c, &target.channel))
+        .ok_or_else(|| anyhow!("频道 {} 不存在", target.channel))?;
+    let addr = lookup_host(target.target.as_str())
+        .await?
+        .find(|a| a.is_ipv4())
+        .ok_or_else(|| anyhow!("无法解析 {}", target.target))?;
+
+    let exists = FORWARDS
+        .lock()
+        .map(|f| {
+            f.values()
+                .any(|f| f.channel_id == channel.id && f.target == addr)
+        })
+        .unwrap_or(false);
+    if exists {
+        return Err(anyhow!("频道 {} 已经在转发到 {}", channel.name, addr));
+    }
+
+    let if_name = target
+        .interface
+        .as_deref()
+        .or(config.iptv.interface(channel.account));
+    let socket = UdpSocket::from_std(udp_socket_with_if(if_name)?)?;
+    socket.connect(addr).await?;
+
+    let group = parse_group_addr(&channel.igmp)?;
+    let subscription = subscribe(group, config.iptv.interface(channel.account), &config.relay)?;
+
+    let forward = Arc::new(Forward {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        channel_id: channel.id,
+        channel: channel.name.clone(),
+        target: addr,
+        rtp: target.rtp,
+        start: Utc::now().timestamp(),
+        packets: AtomicU64::new(0),
+        bytes: AtomicU64::new(0),
+        stop: Notify::new(),
+    });
+    if let Ok(mut forwards) = FORWARDS.lock() {
+        forwards.insert(forward.id, forward.clone());
+    }
+
+    info!(
+        "开始转发 {} ({}) 到 {}{}",
+        channel.name,
+        group,
+        addr,
+        if target.rtp { " (RTP)" } else { "" }
+    );
+    let status = forward.status();
+    tokio::spawn(forward_loop(forward, socket, subscription));
+    Ok(status)
+}
+
+/// 从组播读取 TS，按 7 个 TS 包一组发送到目标地址
+async fn forward_loop(forward: Arc<Forward>, socket: UdpSocket, subscription: Subscription) {
+    let Subscription {
+        group,
+        cached,
+        mut receiver,
+    } = subscription;
+    let mut cached = cached.into_iter();
+    let mut packetizer = RtpPacketizer::new();
+    let mut pending: Vec<u8> = Vec::with_capacity(TS_PACKET_SIZE * TS_PACKETS_PER_DATAGRAM);
+
+    loop {
+        let chunk = match cached.next() {
+            Some(chunk) => chunk,
+            None => tokio::select! {
+                result = receiver.recv() => match result {
+                    Ok(chunk) => chunk,
+                    Err(RecvError::Lagged(skipped)) => {
+                        debug!("转发 {} 跳过 {} 个包", forward.channel, skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = forward.stop.notified() => break,
+            },
+        };
+
+        for pkt in packets(&chunk) {
+            pending.extend_from_slice(pkt);
+            if pending.len() < TS_PACKET_SIZE * TS_PACKETS_PER_DATAGRAM {
+                continue;
+            }
+            let datagram = if forward.rtp {
+                packetizer.packet(&pending)
+            } else {
+                pending.clone()
+            };
+            pending.clear();
+            match socket.send(&datagram).await {
+                Ok(len) => {
+                    forward.packets.fetch_add(1, Ordering::Relaxed);
+                    forward.bytes.fetch_add(len as u64, Ordering::Relaxed);
+                }
+                // 目标暂时不可达（ICMP 端口不可达）时继续发送
+                Err(e) => debug!("转发到 {} 失败: {}", forward.target, e),
+            }
+        }
+    }
+
+    if let Ok(mut forwards) = FORWARDS.lock() {
+        forwards.remove(&forward.id);
+    }
+    info!(
+        "停止转发 {} ({}) 到 {}",
+        forward.channel, group.addr, forward.target
+    );
+}
+
+/// 启动配置中的转发
+pub(crate) async fn forward_startup(config: YamlConfig) {
+    if config.forward.is_empty() {
+        return;
+    }
+
+    let channels = loop {
+        match get_cached_channels(&config.iptv).await {
+            Ok(channels) => break channels,
+            Err(e) => {
+                warn!("转发获取频道列表失败: {}", e);
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    };
+    for target in config.forward.iter() {
+        if let Err(e) = start_forward(&config, &channels, target).await {
+            warn!("转发 {} 到 {} 失败: {}", target.channel, target.target, e);
+        }
+    }
+}
+
+#[get("/api/forwards")]
+pub async fn list_forwards(config: Data<YamlConfig>, req: HttpRequest) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    let mut forwards: Vec<ForwardStatus> = FORWARDS
+        .lock()
+        .map(|f| f.values().map(|f| f.status()).collect())
+        .unwrap_or_default();
+    forwards.sort_by_key(|f| f.id);
+    HttpResponse::Ok().json(forwards)
+}
+
+#[post("/api/forwards")]
+pub async fn add_forward(
+    config: Data<YamlConfig>,
+    body: Json<ForwardTarget>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    let channels = match get_cached_channels(&config.iptv).await {
+        Ok(channels) => channels,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": e.to_string() }))
+        }
+    };
+    match start_forward(&config, &channels, &body).await {
+        Ok(status) => HttpResponse::Created().json(status),
+        Err(e) => limit_response(&e).unwrap_or_else(|| {
+            HttpResponse::BadRequest().json(serde_json::json!({ "error": e.to_string() }))
+        }),
+    }
+}
+
+#[delete("/api/forwards/{id}")]
+pub async fn stop_forward(
+    config: Data<YamlConfig>,
+    path: Path<u64>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    let id = path.into_inner();
+    let forward = FORWARDS.lock().ok().and_then(|f| f.get(&id).cloned());
+    match forward {
+        Some(forward) => {
+            forward.stop.notify_one();
+            HttpResponse::Ok().json(forward.status())
+        }
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "转发不存在" })),
+    }
+}
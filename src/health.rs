@@ -0,0 +1,113 @@
+// health.rs
+// 组播存活检测：定期短暂加入每个频道的组播组，记录最后一次收到数据的时间
+use anyhow::Result;
+use chrono::Utc;
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::YamlConfig;
+use crate::iptv::{get_cached_channels, Channel};
+use crate::limits::LimitExceeded;
+use crate::multicast::{parse_group_addr, subscribe_background};
+use crate::ts::packets;
+
+// channel_id -> 检测结果
+static STATUS: Lazy<RwLock<HashMap<u64, ChannelStatus>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// 频道存活状态
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelStatus {
+    pub alive: bool,
+    /// 最近一次检测时间（秒级时间戳）
+    pub last_check: i64,
+    /// 最近一次收到数据的时间（秒级时间戳）
+    pub last_alive: Option<i64>,
+}
+
+/// 获取频道的检测结果
+pub(crate) fn channel_status(channel_id: u64) -> Option<ChannelStatus> {
+    STATUS.read().ok()?.get(&channel_id).cloned()
+}
+
+/// 频道是否已确认失效（从未检测过的频道不算）
+pub(crate) fn is_dead(channel_id: u64) -> bool {
+    channel_status(channel_id).is_some_and(|s| !s.alive)
+}
+
+/// 记录检测结果
+fn record(channel_id: u64, alive: bool) {
+    let now = Utc::now().timestamp();
+    if let Ok(mut status) = STATUS.write() {
+        let last_alive = status.get(&channel_id).and_then(|s| s.last_alive);
+        status.insert(
+            channel_id,
+            ChannelStatus {
+                alive,
+                last_check: now,
+                last_alive: if alive { Some(now) } else { last_alive },
+            },
+        );
+    }
+}
+
+/// 检测一个频道的组播是否有 TS 数据
+pub(crate) async fn check_channel(config: &YamlConfig, channel: &Channel) -> Result<bool> {
+    let addr = parse_group_addr(&channel.igmp)?;
+    // 只看实时数据，不使用 GOP 缓存；不占用观看的组播上限，达到上限时跳过
+    let mut receiver =
+        subscribe_background(addr, config.iptv.interface(channel.account), &config.relay)?.receiver;
+    let timeout = Duration::from_secs(config.health.timeout);
+
+    let alive = tokio::time::timeout(timeout, async {
+        loop {
+            match receiver.recv().await {
+                Ok(chunk) if packets(&chunk).next().is_some() => return true,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return false,
+            }
+        }
+    })
+    .await
+    .unwrap_or(false);
+
+    debug!("频道 {} ({}) 存活: {}", channel.name, addr, alive);
+    record(channel.id, alive);
+    Ok(alive)
+}
+
+/// 后台定期检测所有频道
+pub(crate) async fn health_loop(config: YamlConfig) {
+    if !config.health.enabled {
+        return;
+    }
+    let delay = Duration::from_secs(config.health.delay);
+    let interval = Duration::from_secs(config.health.interval);
+
+    loop {
+        match get_cached_channels(&config.iptv).await {
+            Ok(channels) => {
+                let mut dead = 0;
+                for channel in channels.iter() {
+                    match check_channel(&config, channel).await {
+                        Ok(true) => {}
+                        Ok(false) => dead += 1,
+                        Err(e) if e.is::<LimitExceeded>() => {
+                            debug!("组播已达上限，跳过检测频道 {}: {}", channel.name, e)
+                        }
+                        Err(e) => warn!("检测频道 {} 失败: {}", channel.name, e),
+                    }
+                    tokio::time::sleep(delay).await;
+                }
+                info!("存活检测完成: {} 个频道，{} 个无数据", channels.len(), dead);
+            }
+            Err(e) => warn!("存活检测前获取频道列表失败: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
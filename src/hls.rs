@@ -0,0 +1,411 @@
+// hls.rs
+// 组播转 HLS：在关键帧处切片，分片保存在内存中的滑动窗口里
+use actix_web::{
+    get,
+    web::{Bytes, Data, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use anyhow::{anyhow, Result};
+use log::{debug, error, info};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::{HlsConfig, YamlConfig};
+use crate::iptv::find_channel;
+use crate::limits::{client_ip, limit_response};
+use crate::multicast::{parse_group_addr, subscribe, Subscription};
+use crate::sessions::{open_session, SessionGuard};
+use crate::sign::check_signature;
+use crate::ts::{packets, PcrClock, TsTracker};
+
+/// 等待第一个分片的轮询间隔
+const WAIT_INTERVAL: Duration = Duration::from_millis(200);
+
+// channel_id -> 正在切片的 HLS 流
+static STREAMS: Lazy<Mutex<HashMap<u64, Arc<HlsStream>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+struct Segment {
+    sequence: u64,
+    duration: f64,
+    data: Bytes,
+}
+
+/// 一个 HLS 客户端，没有持续的连接，按最近一次请求判断是否还在观看
+struct Viewer {
+    /// 会话被断开后为 None，在 idle_timeout 内继续拒绝该客户端的请求
+    session: Option<SessionGuard>,
+    last_access: Instant,
+}
+
+/// 一个频道的 HLS 切片状态
+struct HlsStream {
+    channel: String,
+    segments: RwLock<VecDeque<Segment>>,
+    last_access: Mutex<Instant>,
+    // 客户端 IP -> 会话
+    viewers: Mutex<HashMap<IpAddr, Viewer>>,
+}
+
+impl HlsStream {
+    /// 客户端请求播放列表或分片，新客户端开始一个会话，计入每个客户端的流数量限制
+    fn watch(&self, config: &YamlConfig, channel_id: u64, client: IpAddr) -> Result<()> {
+        let mut viewers = self.viewers.lock().map_err(|_| anyhow!("HLS 会话锁异常"))?;
+        match viewers.get_mut(&client) {
+            Some(viewer) => match &viewer.session {
+                Some(session) if !session.killed() => viewer.last_access = Instant::now(),
+                Some(_) => {
+                    // 结束会话，保留记录到 idle_timeout 后，播放器重新请求时不会立即开始新的会话
+                    viewer.session = None;
+                    viewer.last_access = Instant::now();
+                    return Err(anyhow!("会话已被断开"));
+                }
+                None => return Err(anyhow!("会话已被断开")),
+            },
+            None => {
+                let session = open_session(
+                    config,
+                    client,
+                    "hls",
+                    Some(channel_id),
+                    self.channel.clone(),
+                )?;
+                viewers.insert(
+                    client,
+                    Viewer {
+                        session: Some(session),
+                        last_access: Instant::now(),
+                    },
+                );
+            }
+        }
+        drop(viewers);
+        self.touch();
+        Ok(())
+    }
+
+    /// 记录发送给客户端的分片数据
+    fn record(&self, client: IpAddr, len: usize) {
+        if let Ok(viewers) = self.viewers.lock() {
+            if let Some(session) = viewers.get(&client).and_then(|v| v.session.as_ref()) {
+                session.record(len);
+            }
+        }
+    }
+
+    /// 结束超过 timeout 没有请求的客户端会话，清除超过 timeout 的断开记录
+    fn expire_viewers(&self, timeout: Duration) {
+        if let Ok(mut viewers) = self.viewers.lock() {
+            viewers.retain(|_, v| v.last_access.elapsed() <= timeout);
+        }
+    }
+
+    fn touch(&self) {
+        if let Ok(mut last_access) = self.last_access.lock() {
+            *last_access = Instant::now();
+        }
+    }
+
+    fn idle_time(&self) -> Duration {
+        self.last_access
+            .lock()
+            .map(|t| t.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// 生成 m3u8 播放列表，query 附加在分片地址后（用于传递签名）
+    fn playlist(&self, query: &str) -> Option<String> {
+        let segments = self.segments.read().ok()?;
+        let first = segments.front()?;
+        let target = segments
+            .iter()
+            .map(|s| s.duration.ceil() as u64)
+            .max()
+            .unwrap_or(1);
+
+        let mut m3u8 = format!(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+            target, first.sequence
+        );
+        for segment in segments.iter() {
+            m3u8 += &format!("#EXTINF:{:.3},\n{}.ts", segment.duration, segment.sequence);
+            if !query.is_empty() {
+                m3u8 += &format!("?{}", query);
+            }
+            m3u8 += "\n";
+        }
+        Some(m3u8)
+    }
+
+    fn segment(&self, sequence: u64) -> Option<Bytes> {
+        let segments = self.segments.read().ok()?;
+        segments
+            .iter()
+            .find(|s| s.sequence == sequence)
+            .map(|s| s.data.clone())
+    }
+}
+
+/// 获取频道的 HLS 流，没有时开始切片
+async fn get_or_start(config: &YamlConfig, channel_id: u64) -> Result<Arc<HlsStream>> {
+    if let Some(stream) = STREAMS
+        .lock()
+        .ok()
+        .and_then(|s| s.get(&channel_id).cloned())
+    {
+        return Ok(stream);
+    }
+
+    let channel = find_channel(&config.iptv, channel_id).await?;
+    let addr = parse_group_addr(&channel.igmp)?;
+
+    let mut streams = STREAMS.lock().map_err(|_| anyhow!("HLS 列表锁异常"))?;
+    if let Some(stream) = streams.get(&channel_id) {
+        return Ok(stream.clone());
+    }
+
+    let subscription = subscribe(addr, config.iptv.interface(channel.account), &config.relay)?;
+    let stream = Arc::new(HlsStream {
+        channel: channel.name.clone(),
+        segments: RwLock::new(VecDeque::new()),
+        last_access: Mutex::new(Instant::now()),
+        viewers: Mutex::new(HashMap::new()),
+    });
+    streams.insert(channel_id, stream.clone());
+
+    info!("开始 HLS 切片: 频道 {} ({})", channel.name, addr);
+    tokio::spawn(segment_loop(
+        channel_id,
+        stream.clone(),
+        subscription,
+        config.hls.clone(),
+    ));
+    Ok(stream)
+}
+
+/// 从组播读取 TS，在关键帧处切片
+async fn segment_loop(
+    channel_id: u64,
+    stream: Arc<HlsStream>,
+    subscription: Subscription,
+    config: HlsConfig,
+) {
+    let Subscription {
+        group,
+        cached,
+        mut receiver,
+    } = subscription;
+    // 先处理缓存的 GOP，第一个分片可以更快生成
+    let mut cached = cached.into_iter();
+
+    let target = config.segment_duration.max(1) as f64;
+    let idle_timeout = Duration::from_secs(config.idle_timeout);
+    let mut tracker = TsTracker::default();
+    // 分片时长按 PCR 计算：缓存的 GOP 会被一次性处理，丢包时到达时间也不准
+    let mut clock = PcrClock::default();
+    let mut current: Vec<u8> = Vec::new();
+    let mut started: Option<SegmentStart> = None;
+    let mut sequence = 0u64;
+    let mut last_expire = Instant::now();
+
+    loop {
+        if stream.idle_time() > idle_timeout {
+            stop(channel_id, &stream);
+            info!("HLS 频道 {} 空闲，停止切片 ({})", channel_id, group.addr);
+            return;
+        }
+        // 客户端超过 idle_timeout 没有请求播放列表或分片时结束它的会话
+        if last_expire.elapsed() >= Duration::from_secs(1) {
+            stream.expire_viewers(idle_timeout);
+            last_expire = Instant::now();
+        }
+
+        let next = match cached.next() {
+            Some(chunk) => Ok(Ok(chunk)),
+            None => tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await,
+        };
+        let chunk = match next {
+            Ok(Ok(chunk)) => chunk,
+            Ok(Err(RecvError::Lagged(skipped))) => {
+                debug!("HLS 频道 {} 跳过 {} 个包", channel_id, skipped);
+                continue;
+            }
+            Ok(Err(RecvError::Closed)) => {
+                stop(channel_id, &stream);
+                return;
+            }
+            Err(_) => continue,
+        };
+
+        for pkt in packets(&chunk) {
+            let keyframe = tracker.inspect(pkt);
+            clock.push_packet(pkt);
+
+            // 到达目标时长后在关键帧处切分；长时间没有关键帧时强制切分
+            let cut = match &started {
+                Some(start) => {
+                    let elapsed = start.elapsed(&clock);
+                    (keyframe && elapsed >= target) || elapsed >= target * 3.0
+                }
+                None => keyframe && tracker.ready(),
+            };
+            if cut {
+                if let Some(start) = &started {
+                    let segment = Segment {
+                        sequence,
+                        duration: start.elapsed(&clock),
+                        data: Bytes::from(std::mem::take(&mut current)),
+                    };
+                    sequence += 1;
+                    if let Ok(mut segments) = stream.segments.write() {
+                        segments.push_back(segment);
+                        while segments.len() > config.segment_count.max(1) {
+                            segments.pop_front();
+                        }
+                    }
+                }
+                // 每个分片以 PAT/PMT 开头，保证可以独立解码
+                for psi in tracker.psi_packets() {
+                    current.extend_from_slice(psi);
+                }
+                started = Some(SegmentStart::new(&clock));
+            }
+
+            if started.is_some() {
+                current.extend_from_slice(pkt);
+            }
+        }
+    }
+}
+
+/// 当前分片的开始位置
+struct SegmentStart {
+    /// 开始时的 PCR 时长（秒）
+    media: f64,
+    /// 流中没有 PCR 时按到达时间计算
+    wall: Instant,
+}
+
+impl SegmentStart {
+    fn new(clock: &PcrClock) -> Self {
+        Self {
+            media: clock.seconds(),
+            wall: Instant::now(),
+        }
+    }
+
+    /// 分片已有的时长（秒）
+    fn elapsed(&self, clock: &PcrClock) -> f64 {
+        if clock.has_pcr() {
+            clock.seconds() - self.media
+        } else {
+            self.wall.elapsed().as_secs_f64()
+        }
+    }
+}
+
+/// 停止切片，结束所有客户端的会话
+fn stop(channel_id: u64, stream: &HlsStream) {
+    if let Ok(mut streams) = STREAMS.lock() {
+        streams.remove(&channel_id);
+    }
+    if let Ok(mut viewers) = stream.viewers.lock() {
+        viewers.clear();
+    }
+}
+
+/// 会话被拒绝时的响应
+fn watch_response(channel_id: u64, e: anyhow::Error) -> HttpResponse {
+    if let Some(response) = limit_response(&e) {
+        return response;
+    }
+    debug!("HLS 频道 {}: {}", channel_id, e);
+    HttpResponse::Forbidden().body(e.to_string())
+}
+
+#[get("/hls/{channel_id}/index.m3u8")]
+pub async fn hls_playlist(
+    config: Data<YamlConfig>,
+    path: Path<u64>,
+    req: HttpRequest,
+) -> impl Responder {
+    let channel_id = path.into_inner();
+    debug!("HLS 播放列表: {}", channel_id);
+    if let Some(response) = check_signature(&config, &req) {
+        return response;
+    }
+
+    let stream = match get_or_start(&config, channel_id).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            if let Some(response) = limit_response(&e) {
+                return response;
+            }
+            error!("HLS 频道 {} 启动失败: {}", channel_id, e);
+            return HttpResponse::NotFound().body(format!("HLS 启动失败: {}", e));
+        }
+    };
+    let client = client_ip(&req);
+    if let Err(e) = stream.watch(&config, channel_id, client) {
+        return watch_response(channel_id, e);
+    }
+
+    // 等待第一个分片
+    let deadline = Instant::now() + Duration::from_secs(config.hls.segment_duration * 3 + 5);
+    loop {
+        // 分片沿用播放列表的签名
+        let query = if config.sign.key.is_empty() {
+            ""
+        } else {
+            req.query_string()
+        };
+        if let Some(m3u8) = stream.playlist(query) {
+            return HttpResponse::Ok()
+                .content_type("application/vnd.apple.mpegurl")
+                .insert_header(("Cache-Control", "no-cache"))
+                .body(m3u8);
+        }
+        if Instant::now() > deadline {
+            return HttpResponse::ServiceUnavailable().body("HLS 分片尚未生成");
+        }
+        tokio::time::sleep(WAIT_INTERVAL).await;
+        stream.touch();
+    }
+}
+
+#[get("/hls/{channel_id}/{sequence}.ts")]
+pub async fn hls_segment(
+    config: Data<YamlConfig>,
+    path: Path<(u64, u64)>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (channel_id, sequence) = path.into_inner();
+    if let Some(response) = check_signature(&config, &req) {
+        return response;
+    }
+
+    let stream = match STREAMS
+        .lock()
+        .ok()
+        .and_then(|s| s.get(&channel_id).cloned())
+    {
+        Some(stream) => stream,
+        None => return HttpResponse::NotFound().body("HLS 流不存在"),
+    };
+    let client = client_ip(&req);
+    if let Err(e) = stream.watch(&config, channel_id, client) {
+        return watch_response(channel_id, e);
+    }
+
+    match stream.segment(sequence) {
+        Some(data) => {
+            stream.record(client, data.len());
+            HttpResponse::Ok().content_type("video/mp2t").body(data)
+        }
+        None => HttpResponse::NotFound().body("分片不存在"),
+    }
+}
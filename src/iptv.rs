@@ -1,23 +1,32 @@
-use crate::config::IptvConfig;
+use crate::config::{IptvAccounts, IptvConfig};
+use crate::login::{self, LoginSession};
+use crate::multicast::parse_group_addr;
+use crate::provider::{provider, AuthExpired};
 
 use chrono::{ NaiveDate,  TimeZone, Utc};
 use chrono::Duration as ChronoDuration;
 use anyhow::{Result, Context, anyhow};
-use des::{
-    cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyInit},
-    TdesEde3,
-};
 #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
 use local_ip_address::list_afinet_netifas;
-use log::{debug, info};
-use rand::Rng;
-use regex_lite::Regex;
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
 use reqwest::Client;
-use serde::Deserialize;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::task::JoinSet;
 
-fn get_client_with_if(#[allow(unused_variables)] if_name: Option<&str>) -> Result<Client> {
+/// 频道列表缓存有效期
+pub(crate) const CHANNEL_CACHE_DURATION: Duration = Duration::from_secs(600);
+
+/// 缓存的频道列表和获取时间
+type ChannelCache = Option<(Vec<Channel>, Instant)>;
+
+// 最近一次获取的频道列表，供转发接口按频道 ID 查找播放地址
+static CHANNEL_CACHE: Lazy<RwLock<ChannelCache>> = Lazy::new(|| RwLock::new(None));
+
+pub(crate) fn get_client_with_if(#[allow(unused_variables)] if_name: Option<&str>) -> Result<Client> {
     let timeout = Duration::new(5, 0);
     #[allow(unused_mut)]
     let mut client = Client::builder().timeout(timeout).cookie_store(true);
@@ -42,29 +51,7 @@ fn get_client_with_if(#[allow(unused_variables)] if_name: Option<&str>) -> Resul
     Ok(client.build()?)
 }
 
-async fn get_base_url(client: &Client, args: &IptvConfig) -> Result<String> {
-    let user = args.user.as_str();
-
-    let params = [("Action", "Login"), ("return_type", "1"), ("UserID", user)];
-
-    let url = reqwest::Url::parse_with_params(
-        "http://eds.iptv.gd.cn:8082/EDS/jsp/AuthenticationURL",
-        params,
-    )?;
-
-    let response = client.get(url).send().await?.error_for_status()?;
-
-    let epgurl = reqwest::Url::parse(response.json::<AuthJson>().await?.epgurl.as_str())?;
-    let base_url = format!(
-        "{}://{}:{}",
-        epgurl.scheme(),
-        epgurl.host_str().ok_or(anyhow!("no host"))?,
-        epgurl.port_or_known_default().ok_or(anyhow!("no port"))?,
-    );
-    debug!("Got base_url {base_url}");
-    Ok(base_url)
-}
-
+#[derive(Clone)]
 pub(crate) struct Program {
     pub(crate) start: i64,
     pub(crate) stop: i64,
@@ -72,6 +59,7 @@ pub(crate) struct Program {
     pub(crate) desc: String,
 }
 
+#[derive(Clone)]
 pub(crate) struct Channel {
     pub(crate) id: u64,
     pub(crate) user_channel_id: String,
@@ -79,248 +67,234 @@ pub(crate) struct Channel {
     pub(crate) rtsp: String,
     pub(crate) igmp: String,
     pub(crate) epg: Vec<Program>,
+    /// 可以播放该频道的账号（iptv 配置中的序号）
+    pub(crate) account: usize,
 }
 
-#[derive(Deserialize)]
-struct AuthJson {
-    epgurl: String,
-}
-
-#[derive(Deserialize)]
-struct TokenJson {
-    #[serde(rename = "EncryToken")]
-    encry_token: String,
-}
-
-#[derive(Deserialize)]
-struct PlaybillList {
-    #[serde(rename = "playbillLites")]
-    list: Vec<Bill>,
-}
-
-#[derive(Deserialize)]
-struct Bill {
-    name: String,
-    #[serde(rename = "startTime")]
-    start_time: i64,
-    #[serde(rename = "endTime")]
-    end_time: i64,
-}
-/// 登录 IPTV 系统，返回认证后的 client 和 base_url
-pub(crate) async fn login_iptv(args: &IptvConfig) -> Result<(reqwest::Client, String)> {
-    static mut CACHED_RESULT: Option<(reqwest::Client, String, Instant)> = None;
-    const CACHE_DURATION: Duration = Duration::from_secs(1800);
-    
-    unsafe {
-        // 获取原始指针
-        let cached_ptr = &raw const CACHED_RESULT;
-        
-        // 解引用指针获取 Option
-        match *cached_ptr {
-            Some((ref client, ref base_url, cached_time)) => {
-                if cached_time.elapsed() < CACHE_DURATION {
-                    info!("使用缓存的登录会话");
-                    return Ok((client.clone(), base_url.clone()));
+/// 使用登录会话请求 EPG 服务器，认证失效时作废会话、重新登录后重试一次
+async fn with_session<T, F, Fut>(args: &IptvConfig, request: F) -> Result<T>
+where
+    F: Fn(Arc<LoginSession>) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut retried = false;
+    loop {
+        let session = login::session(args).await?;
+        match request(session.clone()).await {
+            Err(e) if e.is::<AuthExpired>() => {
+                login::invalidate(&session, &e.to_string());
+                if retried {
+                    return Err(e);
                 }
+                retried = true;
             }
-            None => {}
+            result => return result,
         }
     }
-    
-    info!("开始登录 IPTV 系统");
+}
 
-    let start_time = std::time::Instant::now();
-    
-    let user = args.user.as_str();
-    let passwd = args.passwd.as_str();
-    let mac = args.mac.as_str();
-    let imei = args.imei.as_deref().unwrap_or("default_imei");
-    let ip = args.ip.as_deref().unwrap_or("0.0.0.0");
-
-    // 创建客户端
-    let client = get_client_with_if(args.interface.as_deref())?;
-
-    // 获取基础 URL
-    let base_url = get_base_url(&client, args).await?;
-
-    // 第一步：获取 token
-    let params = [
-        ("response_type", "EncryToken"),
-        ("client_id", "smcphone"),
-        ("userid", user),
-    ];
-    let url = reqwest::Url::parse_with_params(
-        format!("{base_url}/EPG/oauth/v2/authorize").as_str(),
-        params,
-    )?;
-    let response = client.get(url).send().await?.error_for_status()?;
-    let token = response.json::<TokenJson>().await?.encry_token;
-    debug!("Got token {token}");
-
-    // 第二步：生成认证信息
-    let enc = ecb::Encryptor::<TdesEde3>::new_from_slice(
-        format!("{:X}", md5::compute(passwd.as_bytes()))[0..24].as_bytes(),
-    );
-    let enc = match enc {
-        Ok(enc) => Ok(enc),
-        Err(e) => Err(std::io::Error::new(
-            std::io::ErrorKind::Unsupported,
-            format!("Encrypt error {e}"),
-        )),
-    }?;
-    
-    let data = format!(
-        "{}${token}${user}${imei}${ip}${mac}$$CTC",
-        rand::thread_rng().gen_range(0..10000000),
-    );
-    let auth = hex::encode_upper(enc.encrypt_padded_vec_mut::<Pkcs7>(data.as_bytes()));
-    debug!("Got auth {auth}");
-
-    // 第三步：获取访问令牌
-    let params = [
-        ("client_id", "smcphone"),
-        ("DeviceType", "deviceType"),
-        ("UserID", user),
-        ("DeviceVersion", "deviceVersion"),
-        ("userdomain", "2"),
-        ("datadomain", "3"),
-        ("accountType", "1"),
-        ("authinfo", auth.as_str()),
-        ("grant_type", "EncryToken"),
-    ];
-    let url = reqwest::Url::parse_with_params(
-        format!("{base_url}/EPG/oauth/v2/token").as_str(),
-        params,
-    )?;
-    
-    let _response = client.get(url).send().await?.error_for_status()?;
-    
-    // 缓存结果
-    unsafe {
-        CACHED_RESULT = Some((client.clone(), base_url.clone(), Instant::now()));
+/// 获取一个账号的频道列表
+async fn get_account_channels(args: &IptvConfig, account: usize) -> Result<Vec<Channel>> {
+    let mut channels = with_session(args, |session| async move {
+        provider(args)
+            .channels(&session.client, &session.base_url)
+            .await
+    })
+    .await?;
+
+    // 统一成 igmp://[源地址@]组地址:端口，IPv6 地址加上方括号
+    for channel in channels.iter_mut() {
+        if let Ok(addr) = parse_group_addr(&channel.igmp) {
+            channel.igmp = format!("igmp://{}", addr);
+        }
+        channel.account = account;
     }
-    
-    let elapsed = start_time.elapsed();
-    info!("成功登录 IPTV 系统，耗时: {:?}", elapsed);
-    
-    Ok((client, base_url))
+    Ok(channels)
 }
 
 /// 获取频道列表（不包含 EPG）
-pub(crate) async fn get_channel_list(
-    client: &reqwest::Client,
-    base_url: &str,
-) -> Result<Vec<Channel>> {
+///
+/// 多个账号时合并各账号的频道，ID 或名称相同的频道只保留排在前面的账号的。
+/// 部分账号获取失败时使用其余账号的频道。
+pub(crate) async fn get_channel_list(accounts: &IptvAccounts) -> Result<Vec<Channel>> {
     info!("Fetching channel list");
     let start_time = std::time::Instant::now();
-    
-    let url = reqwest::Url::parse(
-        format!("{}/EPG/jsp/getchannellistHWCTC.jsp", base_url).as_str()
-    )?;
-    
-    let response = client.get(url).send().await?.error_for_status()?;
-    let res = response.text().await?;
-    
-    let channel_pattern = Regex::new(
-        r#"(?m)Authentication.CTCSetConfig([^"]*)ChannelID="([^"]*)",ChannelName="([^"]*)",UserChannelID="([^"]*)",ChannelURL="([^|]*)\|([^"]*)",(.*?)TimeShiftURL="([^"]*)""#
-    )?;
-    
+
     let mut channels = Vec::new();
-    
-    for cap in channel_pattern.captures_iter(&res) {
-        let channel_id = cap[2].to_string();
-        let channel_name = cap[3].to_string().replace('＋', "+").replace(' ', "").replace('-', "");
-        let user_channel_id = cap[4].to_string();
-        let igmp = cap[5].to_string();
-        let time_shift_url = cap[8].to_string();
-        
-        let channel_id = channel_id.parse::<u64>()
-            .unwrap_or_else(|_| {
-                channel_id.as_str().chars().map(|c| c as u64).sum()
-            });
-        
-        debug!("Found channel: {} (ID: {})", channel_name, channel_id);
-        
-        let channel = Channel {
-            id: channel_id,
-            user_channel_id,
-            name: channel_name,
-            rtsp: time_shift_url,
-            igmp,
-            epg: Vec::new(),
+    let mut ids = HashSet::new();
+    let mut names = HashSet::new();
+    let mut last_error = None;
+    for (account, args) in accounts.iter().enumerate() {
+        let list = match get_account_channels(args, account).await {
+            Ok(list) => list,
+            Err(e) => {
+                warn!("获取 {} 的频道列表失败: {}", args.user, e);
+                last_error = Some(e);
+                continue;
+            }
         };
-        channels.push(channel);
+        let count = list.len();
+        for channel in list {
+            if ids.contains(&channel.id) || names.contains(&channel.name) {
+                debug!("频道 {} (ID: {}) 已由其他账号提供", channel.name, channel.id);
+                continue;
+            }
+            ids.insert(channel.id);
+            names.insert(channel.name.clone());
+            channels.push(channel);
+        }
+        debug!("{} 提供 {} 个频道", args.user, count);
     }
-    
-    info!("Got {} channel(s)", channels.len());
+    if channels.is_empty() {
+        if let Some(e) = last_error {
+            return Err(e);
+        }
+    }
+
     let elapsed = start_time.elapsed();
     println!("📡 获取频道列表... in {:?}", elapsed);
     Ok(channels)
 }
 
 pub(crate) async fn get_channels(
-    args: &IptvConfig,
+    accounts: &IptvAccounts,
 
 ) -> Result<Vec<Channel>> {
     info!("Obtaining channels");
 
-    // 1. 登录获取认证后的客户端
-    let (client, base_url) = login_iptv(args).await?;
-    
-    // 2. 获取频道列表
-    let channels = get_channel_list(&client, &base_url).await?;
+    // 1. 获取频道列表
+    let channels = get_channel_list(accounts).await?;
+
+    // 2. 更新缓存
+    if let Ok(mut cache) = CHANNEL_CACHE.write() {
+        *cache = Some((channels.clone(), Instant::now()));
+    }
 
     Ok(channels)
 }
 
+/// 获取频道列表，优先使用缓存
+pub(crate) async fn get_cached_channels(accounts: &IptvAccounts) -> Result<Vec<Channel>> {
+    if let Ok(cache) = CHANNEL_CACHE.read() {
+        if let Some((channels, cached_time)) = cache.as_ref() {
+            if cached_time.elapsed() < CHANNEL_CACHE_DURATION {
+                return Ok(channels.clone());
+            }
+        }
+    }
+    get_channels(accounts).await
+}
+
+/// 在已缓存的频道列表中查找，不会触发登录
+pub(crate) fn cached_channel(predicate: impl Fn(&Channel) -> bool) -> Option<Channel> {
+    CHANNEL_CACHE
+        .read()
+        .ok()?
+        .as_ref()?
+        .0
+        .iter()
+        .find(|c| predicate(c))
+        .cloned()
+}
+
+/// 按频道 ID 查找频道
+pub(crate) async fn find_channel(accounts: &IptvAccounts, channel_id: u64) -> Result<Channel> {
+    get_cached_channels(accounts)
+        .await?
+        .into_iter()
+        .find(|c| c.id == channel_id)
+        .ok_or_else(|| anyhow!("频道 {} 不存在", channel_id))
+}
+
 pub(crate) async fn get_channels_epg(
-    args: &IptvConfig,
+    accounts: &IptvAccounts,
 
 ) -> Result<Vec<Channel>> {
 
     let start_time = std::time::Instant::now();
 
-    // 1. 登录获取认证后的客户端
-    let (client, base_url) = login_iptv(args).await?;
-    
-    // 2. 获取频道列表
-    let channels = get_channel_list(&client, &base_url).await?;
+    // 1. 获取频道列表，必要时登录
+    let channels = get_channel_list(accounts).await?;
+
+    // 2. 每个账号使用同一个登录会话获取节目单
+    let mut sessions = Vec::new();
+    for args in accounts.iter() {
+        sessions.push(match login::session(args).await {
+            Ok(session) => Some(session),
+            Err(e) => {
+                warn!("{} 登录失败，跳过该账号的节目单: {}", args.user, e);
+                None
+            }
+        });
+    }
 
-    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
     let begin_timestamp = now - 86400000 * 7;
     let end_timestamp = now + 86400000 * 2;
     let mut tasks = JoinSet::new();
 
-    for channel in channels.into_iter() {
-        let params = [
-            ("channelId", format!("{}", channel.id)),
-            ("begin", format!("{}", begin_timestamp)),
-            ("end", format!("{}", end_timestamp)),
-        ];
-
+    for mut channel in channels.into_iter() {
         info!("请求参数:channle_id={} begin={}, end={}",channel.id  , begin_timestamp, end_timestamp);
 
-        let url = reqwest::Url::parse_with_params(
-            format!("{base_url}/EPG/jsp/iptvsnmv3/en/play/ajax/_ajax_getPlaybillList.jsp").as_str(),
-            params,
-        )?;
-        let client = client.clone();
-        tasks.spawn(async move { (client.get(url).send().await, channel) });
+        let Some(session) = sessions[channel.account].clone() else {
+            tasks.spawn(async move { (channel, false) });
+            continue;
+        };
+        let provider = provider(accounts.account(channel.account));
+        tasks.spawn(async move {
+            let result = provider
+                .playbill(&session.client, &session.base_url, channel.id, begin_timestamp, end_timestamp)
+                .await;
+            match result {
+                Ok(epg) => {
+                    debug!("获得节目单信息:channle_id={} begin={}, end={}, 数量={}",channel.id  , begin_timestamp, end_timestamp, epg.len());
+                    channel.epg = epg;
+                }
+                // 会话失效时作废会话，该频道稍后用重新登录的会话再请求
+                Err(e) if e.is::<AuthExpired>() => {
+                    login::invalidate(&session, &e.to_string());
+                    return (channel, true);
+                }
+                Err(e) => debug!("获取频道 {} 节目单失败: {}", channel.id, e),
+            }
+            (channel, false)
+        });
     }
     let mut channels = vec![];
-    while let Some(Ok((Ok(res), mut channel))) = tasks.join_next().await {
-        if let Ok(play_bill_list) = res.json::<PlaybillList>().await {
-            debug!("获得节目单信息:channle_id={} begin={}, end={}, 数量={}",channel.id  , begin_timestamp, end_timestamp, play_bill_list.list.len());
-            for bill in play_bill_list.list.into_iter() {
-                channel.epg.push(Program {
-                    start: bill.start_time,
-                    stop: bill.end_time,
-                    title: bill.name.clone(),
-                    desc: bill.name,
+    let mut expired = vec![];
+    while let Some(Ok((channel, retry))) = tasks.join_next().await {
+        if retry {
+            expired.push(channel);
+        } else {
+            channels.push(channel);
+        }
+    }
+
+    // 3. 会话失效的频道重新登录后再获取一次
+    if !expired.is_empty() {
+        info!("会话失效，重新获取 {} 个频道的节目单", expired.len());
+        let mut tasks = JoinSet::new();
+        for mut channel in expired.into_iter() {
+            let args = accounts.account(channel.account).clone();
+            tasks.spawn(async move {
+                let provider = provider(&args);
+                let channel_id = channel.id;
+                let result = with_session(&args, |session| async move {
+                    provider
+                        .playbill(&session.client, &session.base_url, channel_id, begin_timestamp, end_timestamp)
+                        .await
                 })
-            }
+                .await;
+                match result {
+                    Ok(epg) => channel.epg = epg,
+                    Err(e) => debug!("获取频道 {} 节目单失败: {}", channel.id, e),
+                }
+                channel
+            });
+        }
+        while let Some(Ok(channel)) = tasks.join_next().await {
+            channels.push(channel);
         }
-        channels.push(channel);
     }
 
     let elapsed: Duration = start_time.elapsed();
@@ -331,16 +305,12 @@ pub(crate) async fn get_channels_epg(
 
 /// 获取指定频道在指定日期的 EPG 数据
 pub(crate) async fn get_channel_date_epg(
-    args: &IptvConfig,
+    accounts: &IptvAccounts,
     channel_id: u64,
     date: &str,
 ) -> Result<Channel> {
     info!("获取频道 {} 在 {} 的 EPG 数据", channel_id, date);
 
-    // 1. 登录获取认证后的客户端
-    let (client, base_url) = login_iptv(args).await?;
-
-
     let mut target_channel  = Channel {
         id: channel_id,
         user_channel_id: String::new(),
@@ -348,8 +318,15 @@ pub(crate) async fn get_channel_date_epg(
         rtsp: String::new(),
         igmp: String::new(),
         epg: Vec::new(),
+        account: 0,
     };
 
+    // 使用提供该频道的账号，频道列表还没有缓存时使用第一个账号
+    if let Some(channel) = cached_channel(|c| c.id == channel_id) {
+        target_channel.account = channel.account;
+    }
+    let args = accounts.account(target_channel.account);
+
     let start_time = std::time::Instant::now();
 
     // 3. 解析日期，获取时间范围
@@ -358,33 +335,15 @@ pub(crate) async fn get_channel_date_epg(
     debug!("请求参数: channel_id={} date={} begin={} end={}", 
           channel_id, date, begin_timestamp, end_timestamp);
 
-    // 4. 只获取目标频道的 EPG
-    let params = [
-        ("channelId", format!("{}", channel_id)),
-        ("begin", format!("{}", begin_timestamp)),
-        ("end", format!("{}", end_timestamp)),
-    ];
-
-    let url = reqwest::Url::parse_with_params(
-        format!("{}/EPG/jsp/iptvsnmv3/en/play/ajax/_ajax_getPlaybillList.jsp", base_url).as_str(),
-        params,
-    )?;
-
-    // 5. 发送请求
-    let response = client.get(url).send().await?.error_for_status()?;
-    
-    let play_bill_list: PlaybillList = response.json().await?;
-
-    // 8. 填充 EPG 数据
-    target_channel.epg.clear(); // 清空现有 EPG 数据
-    for bill in play_bill_list.list.into_iter() {
-        debug!("EPG: {} - {}", bill.start_time, bill.name);
-        target_channel.epg.push(Program {
-            start: bill.start_time,
-            stop: bill.end_time,
-            title: bill.name.clone(),
-            desc: bill.name,
-        });
+    // 4. 只获取目标频道的 EPG，必要时登录
+    target_channel.epg = with_session(args, |session| async move {
+        provider(args)
+            .playbill(&session.client, &session.base_url, channel_id, begin_timestamp, end_timestamp)
+            .await
+    })
+    .await?;
+    for program in target_channel.epg.iter() {
+        debug!("EPG: {} - {}", program.start, program.title);
     }
 
     let elapsed = start_time.elapsed();
@@ -432,16 +391,14 @@ fn cal_date_range(date_str: &str) -> Result<(i64, i64)> {
 }
 
 
-pub(crate) async fn get_icon(args: &IptvConfig, id: &str) -> Result<Vec<u8>> {
-    let client = get_client_with_if(args.interface.as_deref())?;
-
-    let base_url = get_base_url(&client, args).await?;
-
-    let url = reqwest::Url::parse(&format!(
-        "{base_url}/EPG/jsp/iptvsnmv3/en/list/images/channelIcon/{}.png",
-        id
-    ))?;
-
-    let response = client.get(url).send().await?.error_for_status()?;
-    Ok(response.bytes().await?.to_vec())
+/// 获取台标，依次尝试每个账号
+pub(crate) async fn get_icon(accounts: &IptvAccounts, id: &str) -> Result<Vec<u8>> {
+    let mut last_error = anyhow!("没有可用的账号");
+    for args in accounts.iter() {
+        match provider(args).icon(args, id).await {
+            Ok(icon) => return Ok(icon),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
 }
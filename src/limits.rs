@@ -0,0 +1,29 @@
+// limits.rs
+// 并发限制：超出组播组数量、客户端流数量或总码率上限时直接拒绝，返回 503
+use actix_web::{HttpRequest, HttpResponse};
+use std::net::{IpAddr, Ipv4Addr};
+
+/// 超出并发限制
+#[derive(Debug)]
+pub(crate) struct LimitExceeded(pub(crate) String);
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// 请求的客户端 IP
+pub(crate) fn client_ip(req: &HttpRequest) -> IpAddr {
+    req.peer_addr()
+        .map(|a| a.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+/// 超出限制时的 503 响应，其他错误返回 None
+pub(crate) fn limit_response(e: &anyhow::Error) -> Option<HttpResponse> {
+    e.downcast_ref::<LimitExceeded>()
+        .map(|e| HttpResponse::ServiceUnavailable().body(e.to_string()))
+}
@@ -0,0 +1,266 @@
+// login.rs
+// IPTV 登录会话管理：每个账号一个会话，同一时间只进行一次登录，过期前主动刷新，上游认证失效时重新登录
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use reqwest::Client;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::IptvConfig;
+use crate::provider::provider;
+
+/// 刷新失败后的重试间隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+// 每个 IPTV 账号（按 iptv 配置中的序号）的登录会话
+static ACCOUNTS: Lazy<Mutex<HashMap<usize, Arc<Account>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 一次登录得到的会话
+pub(crate) struct LoginSession {
+    pub(crate) client: Client,
+    pub(crate) base_url: String,
+    /// 账号在 iptv 配置中的序号
+    account: usize,
+    user: String,
+    id: u64,
+    created: Instant,
+}
+
+/// 登录会话的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginState {
+    /// 还没有登录
+    LoggedOut,
+    /// 没有可用会话，正在登录
+    LoggingIn,
+    /// 会话可用
+    Active,
+    /// 会话仍然可用，正在提前重新登录
+    Refreshing,
+    /// 会话已超过有效期
+    Expired,
+    /// 上游认证失效，等待重新登录
+    Invalidated,
+    /// 最近一次登录失败
+    Failed,
+}
+
+struct Account {
+    manager: Mutex<Manager>,
+    // 保证同一账号同一时间只有一个登录在进行
+    login_lock: tokio::sync::Mutex<()>,
+}
+
+struct Manager {
+    session: Option<Arc<LoginSession>>,
+    state: LoginState,
+    logins: u64,
+    /// 最近一次登录成功的时间（秒级时间戳）
+    last_login: Option<i64>,
+    last_error: Option<String>,
+}
+
+/// 登录会话状态，用于状态接口
+#[derive(Debug, Serialize)]
+pub struct LoginStatus {
+    /// 账号在 iptv 配置中的序号
+    pub account: usize,
+    pub user: String,
+    pub state: LoginState,
+    /// 会话已存在的时间（秒）
+    pub age: Option<u64>,
+    pub base_url: Option<String>,
+    pub logins: u64,
+    pub last_login: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+impl LoginSession {
+    fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+}
+
+fn account(index: usize) -> Arc<Account> {
+    let mut accounts = ACCOUNTS.lock().unwrap_or_else(|e| e.into_inner());
+    accounts
+        .entry(index)
+        .or_insert_with(|| {
+            Arc::new(Account {
+                manager: Mutex::new(Manager {
+                    session: None,
+                    state: LoginState::LoggedOut,
+                    logins: 0,
+                    last_login: None,
+                    last_error: None,
+                }),
+                login_lock: tokio::sync::Mutex::new(()),
+            })
+        })
+        .clone()
+}
+
+fn ttl(args: &IptvConfig) -> Duration {
+    Duration::from_secs(args.session_ttl)
+}
+
+/// 过期前多久开始刷新，最多为有效期的一半
+fn refresh_margin(args: &IptvConfig) -> Duration {
+    Duration::from_secs(args.session_refresh).min(ttl(args) / 2)
+}
+
+/// 当前仍在有效期内的会话
+fn current(args: &IptvConfig) -> Option<Arc<LoginSession>> {
+    account(args.index)
+        .manager
+        .lock()
+        .ok()?
+        .session
+        .clone()
+        .filter(|s| s.age() < ttl(args))
+}
+
+/// 登录并保存会话，调用前需要持有该账号的 login_lock
+async fn relogin(args: &IptvConfig) -> Result<Arc<LoginSession>> {
+    let account = account(args.index);
+    if let Ok(mut manager) = account.manager.lock() {
+        manager.state = if manager
+            .session
+            .as_ref()
+            .is_some_and(|s| s.age() < ttl(args))
+        {
+            LoginState::Refreshing
+        } else {
+            LoginState::LoggingIn
+        };
+    }
+
+    info!("开始登录 IPTV 系统: {}", args.user);
+    let start_time = Instant::now();
+    let result = provider(args).login(args).await;
+    let mut manager = account
+        .manager
+        .lock()
+        .map_err(|_| anyhow!("登录会话锁异常"))?;
+    match result {
+        Ok((client, base_url)) => {
+            info!("成功登录 IPTV 系统，耗时: {:?}", start_time.elapsed());
+            let session = Arc::new(LoginSession {
+                client,
+                base_url,
+                account: args.index,
+                user: args.user.clone(),
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                created: Instant::now(),
+            });
+            manager.session = Some(session.clone());
+            manager.state = LoginState::Active;
+            manager.logins += 1;
+            manager.last_login = Some(Utc::now().timestamp());
+            manager.last_error = None;
+            Ok(session)
+        }
+        Err(e) => {
+            // 旧会话仍在有效期内时继续使用
+            manager.state = LoginState::Failed;
+            manager.last_error = Some(e.to_string());
+            Err(e)
+        }
+    }
+}
+
+/// 获取账号的登录会话，没有有效会话时登录，并发请求只会触发一次登录
+pub(crate) async fn session(args: &IptvConfig) -> Result<Arc<LoginSession>> {
+    if let Some(session) = current(args) {
+        return Ok(session);
+    }
+
+    let account = account(args.index);
+    let _guard = account.login_lock.lock().await;
+    // 等待期间其他请求可能已经登录成功
+    if let Some(session) = current(args) {
+        return Ok(session);
+    }
+    relogin(args).await
+}
+
+/// 上游认证失效时作废会话，下次请求会重新登录
+///
+/// 只作废传入的会话，避免把其他请求刚登录的新会话也作废。
+pub(crate) fn invalidate(session: &LoginSession, reason: &str) {
+    if let Ok(mut manager) = account(session.account).manager.lock() {
+        if manager.session.as_ref().is_some_and(|s| s.id == session.id) {
+            warn!("{} 的登录会话已失效: {}", session.user, reason);
+            manager.session = None;
+            manager.state = LoginState::Invalidated;
+            manager.last_error = Some(reason.to_string());
+        }
+    }
+}
+
+/// 账号当前登录会话的状态
+pub(crate) fn login_status(args: &IptvConfig) -> LoginStatus {
+    let account = account(args.index);
+    let Ok(manager) = account.manager.lock() else {
+        return LoginStatus {
+            account: args.index,
+            user: args.user.clone(),
+            state: LoginState::Failed,
+            age: None,
+            base_url: None,
+            logins: 0,
+            last_login: None,
+            last_error: Some("登录会话锁异常".to_string()),
+        };
+    };
+    let age = manager.session.as_ref().map(|s| s.age());
+    let state = match (manager.state, age) {
+        (LoginState::Active, Some(age)) if age >= ttl(args) => LoginState::Expired,
+        (state, _) => state,
+    };
+    LoginStatus {
+        account: args.index,
+        user: args.user.clone(),
+        state,
+        age: age.map(|a| a.as_secs()),
+        base_url: manager.session.as_ref().map(|s| s.base_url.clone()),
+        logins: manager.logins,
+        last_login: manager.last_login,
+        last_error: manager.last_error.clone(),
+    }
+}
+
+/// 后台在账号的会话过期前主动重新登录
+pub(crate) async fn refresh_loop(args: IptvConfig) {
+    let account = account(args.index);
+    let refresh_at = ttl(&args) - refresh_margin(&args);
+
+    loop {
+        let wait = current(&args)
+            .map(|s| refresh_at.saturating_sub(s.age()))
+            .unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        let result = {
+            let _guard = account.login_lock.lock().await;
+            // 等待期间会话可能已经被其他请求刷新
+            match current(&args) {
+                Some(session) if session.age() < refresh_at => Ok(()),
+                _ => relogin(&args).await.map(|_| ()),
+            }
+        };
+        if let Err(e) = result {
+            warn!("刷新 {} 的登录会话失败: {}", args.user, e);
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+}
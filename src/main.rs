@@ -6,10 +6,29 @@ use actix_web::{
 use std::path::PathBuf;
 
 
+mod api;
 mod args;
+mod catchup;
 mod config;
+mod dvr;
+mod forward;
+mod health;
+mod hls;
 mod iptv;
+mod limits;
+mod login;
+mod multicast;
+mod play;
+mod probe;
+mod provider;
+mod relay;
 mod routes;
+mod rtp;
+mod rtsp;
+mod sessions;
+mod sign;
+mod timeshift;
+mod ts;
 mod utils;
 
 use args::Args;
@@ -55,14 +74,31 @@ async fn main() -> std::io::Result<()> {
 
     init_logger_simple(&yaml_config);
 
-    println!("📡 iptv账号:{}  密码:{}", 
-        yaml_config.iptv.user, 
-        mask_password(&yaml_config.iptv.passwd)
-    );
+    for account in yaml_config.iptv.iter() {
+        println!("📡 iptv账号:{}  密码:{}", 
+            account.user, 
+            mask_password(&account.passwd)
+        );
+    }
 
     let listen_addr = yaml_config.server.listen.clone();
     let workers = yaml_config.server.workers;
 
+    // 后台在每个账号的登录会话过期前重新登录
+    for account in yaml_config.iptv.iter() {
+        actix_web::rt::spawn(login::refresh_loop(account.clone()));
+    }
+    // 后台探测频道信息
+    actix_web::rt::spawn(probe::probe_loop(yaml_config.clone()));
+    // 后台检测组播是否有数据
+    actix_web::rt::spawn(health::health_loop(yaml_config.clone()));
+    // 后台按 EPG 调度录制
+    actix_web::rt::spawn(dvr::dvr_loop(yaml_config.clone()));
+    // 后台本地时移缓冲
+    actix_web::rt::spawn(timeshift::timeshift_loop(yaml_config.clone()));
+    // 启动配置中的组播转单播
+    actix_web::rt::spawn(forward::forward_startup(yaml_config.clone()));
+
     let server = HttpServer::new(move || {
         let config_data = Data::new(yaml_config.clone());
         App::new()
@@ -70,6 +106,30 @@ async fn main() -> std::io::Result<()> {
             .service(routes::playlist)
             .service(routes::logo)
             .service(routes::epg)
+            .service(relay::udp)
+            .service(relay::rtp)
+            .service(rtsp::rtsp)
+            .service(play::play)
+            .service(play::play_by_name)
+            .service(catchup::catchup)
+            .service(catchup::catchup_flussonic)
+            .service(timeshift::timeshift)
+            .service(hls::hls_playlist)
+            .service(hls::hls_segment)
+            .service(api::channel_list)
+            .service(api::status)
+            .service(sessions::session_history)
+            .service(sessions::list_sessions)
+            .service(sessions::kill_session)
+            .service(forward::list_forwards)
+            .service(forward::add_forward)
+            .service(forward::stop_forward)
+            .service(dvr::list_rules)
+            .service(dvr::add_rule)
+            .service(dvr::delete_rule)
+            .service(dvr::list_recordings)
+            .service(dvr::add_recording)
+            .service(dvr::delete_recording)
             .app_data(config_data)
     })
     .workers(workers)
@@ -81,6 +141,18 @@ async fn main() -> std::io::Result<()> {
         println!("📺 XMLTV 地址: http://{}/xmltv", addr);
         println!("📋 播放列表地址: http://{}/playlist", addr);
         println!("🖼️ Logo 地址: http://{}/logo", addr);
+        println!("📡 组播转发地址: http://{}/udp/{{group}}:{{port}}", addr);
+        println!("▶️ 频道播放地址: http://{}/play/{{channel_id}}", addr);
+        println!("▶️ 按名称播放地址: http://{}/play/by-name/{{tvgname}}", addr);
+        println!("🍎 HLS 地址: http://{}/hls/{{channel_id}}/index.m3u8", addr);
+        println!("⏪ 回看转发地址: http://{}/rtsp/{{host}}/{{path}}", addr);
+        println!("⏳ 本地时移地址: http://{}/timeshift/{{channel_id}}?playseek=", addr);
+        println!("🕘 回看跳转地址: http://{}/catchup/{{channel_id}}?utc={{utc}}", addr);
+        println!("🔎 频道信息接口: http://{}/api/channels", addr);
+        println!("🩺 状态接口: http://{}/api/status", addr);
+        println!("👀 会话接口: http://{}/api/sessions", addr);
+        println!("📤 单播转发接口: http://{}/api/forwards", addr);
+        println!("⏺️ 录制接口: http://{}/api/recordings", addr);
     }
     
     server.run().await
@@ -0,0 +1,678 @@
+// multicast.rs
+// 组播组管理：每个组只加入一次，所有 HTTP 客户端共享同一个环形缓冲区
+use actix_web::web::Bytes;
+use anyhow::{anyhow, Result};
+use log::{debug, error, info};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
+
+use crate::config::RelayConfig;
+use crate::limits::LimitExceeded;
+use crate::rtp::{RtpDepacketizer, RtpStats};
+use crate::ts::{packets, TsQuality, TsTracker, TS_PACKET_SIZE, TS_SYNC_BYTE};
+use crate::utils::{interface_index, interface_ipv4};
+
+/// 单个 UDP 包最大长度
+const MAX_PACKET_SIZE: usize = 65536;
+
+/// 检查空闲状态的间隔
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 统计码率的时间窗口
+const BITRATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// 码率历史的采样间隔
+const BITRATE_HISTORY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 码率历史保留的采样数
+const BITRATE_HISTORY_LEN: usize = 60;
+
+// 当前已加入的组播组
+static GROUPS: Lazy<Mutex<HashMap<GroupAddr, Arc<Group>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 组播地址，SSM 时带有源地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct GroupAddr {
+    pub(crate) source: Option<IpAddr>,
+    pub(crate) group: SocketAddr,
+}
+
+impl fmt::Display for GroupAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source {
+            Some(IpAddr::V6(source)) => write!(f, "[{}]@{}", source, self.group),
+            Some(source) => write!(f, "{}@{}", source, self.group),
+            None => write!(f, "{}", self.group),
+        }
+    }
+}
+
+/// 一个已加入的组播组
+pub(crate) struct Group {
+    pub(crate) addr: GroupAddr,
+    pub(crate) rtp_stats: RtpStats,
+    /// 累计收到的字节数
+    bytes: AtomicU64,
+    /// 最近一个统计窗口的码率（bit/s）
+    bitrate: AtomicU64,
+    /// 每个采样间隔的平均码率（kbps），最新的在最后
+    bitrate_history: Mutex<VecDeque<u64>>,
+    quality: Mutex<TsQuality>,
+    sender: broadcast::Sender<Bytes>,
+    gop: Mutex<GopCache>,
+    /// 只有后台检测、探测在使用，不计入上限，空闲时立即退出
+    background: AtomicBool,
+}
+
+/// 组播组状态，用于状态接口
+#[derive(Debug, Serialize)]
+pub struct GroupStatus {
+    pub addr: String,
+    pub clients: usize,
+    pub bytes: u64,
+    /// 当前码率（kbps）
+    pub bitrate: u64,
+    pub rtp_received: u64,
+    pub rtp_lost: u64,
+    pub rtp_duplicate: u64,
+    pub rtp_reordered: u64,
+    /// 最近的码率历史（kbps），每 10 秒一个采样
+    pub bitrate_history: Vec<u64>,
+    pub ts_packets: u64,
+    /// 连续计数器错误
+    pub cc_errors: u64,
+    pub pcr_discontinuities: u64,
+    /// PCR 抖动（毫秒）
+    pub pcr_jitter_ms: f64,
+    pub pcr_jitter_max_ms: f64,
+}
+
+impl Group {
+    pub(crate) fn status(&self) -> GroupStatus {
+        let quality = self.quality.lock();
+        let quality = quality.as_deref();
+        GroupStatus {
+            addr: self.addr.to_string(),
+            clients: self.sender.receiver_count(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            bitrate: self.bitrate.load(Ordering::Relaxed) / 1000,
+            rtp_received: self.rtp_stats.received.load(Ordering::Relaxed),
+            rtp_lost: self.rtp_stats.lost.load(Ordering::Relaxed),
+            rtp_duplicate: self.rtp_stats.duplicate.load(Ordering::Relaxed),
+            rtp_reordered: self.rtp_stats.reordered.load(Ordering::Relaxed),
+            bitrate_history: self
+                .bitrate_history
+                .lock()
+                .map(|h| h.iter().copied().collect())
+                .unwrap_or_default(),
+            ts_packets: quality.map(|q| q.packets).unwrap_or_default(),
+            cc_errors: quality.map(|q| q.cc_errors).unwrap_or_default(),
+            pcr_discontinuities: quality.map(|q| q.pcr_discontinuities).unwrap_or_default(),
+            pcr_jitter_ms: quality.map(|q| q.pcr_jitter_ms).unwrap_or_default(),
+            pcr_jitter_max_ms: quality.map(|q| q.pcr_jitter_max_ms).unwrap_or_default(),
+        }
+    }
+}
+
+/// 当前已加入的所有组播组
+pub(crate) fn active_groups() -> Vec<GroupStatus> {
+    GROUPS
+        .lock()
+        .map(|groups| groups.values().map(|g| g.status()).collect())
+        .unwrap_or_default()
+}
+
+/// 订阅结果：先发送缓存的 GOP，再接收实时数据
+pub(crate) struct Subscription {
+    pub(crate) group: Arc<Group>,
+    pub(crate) cached: Vec<Bytes>,
+    pub(crate) receiver: broadcast::Receiver<Bytes>,
+}
+
+/// 最近一个 GOP 的缓存，以 PAT/PMT 开头，从关键帧开始
+struct GopCache {
+    enabled: bool,
+    max_bytes: usize,
+    tracker: TsTracker,
+    chunks: Vec<Bytes>,
+    bytes: usize,
+}
+
+impl GopCache {
+    fn new(enabled: bool, max_bytes: usize) -> Self {
+        Self {
+            enabled,
+            max_bytes,
+            tracker: TsTracker::default(),
+            chunks: Vec::new(),
+            bytes: 0,
+        }
+    }
+
+    /// 处理一段 TS 数据，遇到关键帧时重新开始缓存
+    fn push(&mut self, chunk: &Bytes) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut keyframe_at = None;
+        for (i, pkt) in chunk.chunks_exact(TS_PACKET_SIZE).enumerate() {
+            if pkt[0] == TS_SYNC_BYTE && self.tracker.inspect(pkt) && self.tracker.ready() {
+                keyframe_at = Some(i * TS_PACKET_SIZE);
+            }
+        }
+
+        match keyframe_at {
+            Some(offset) => {
+                self.chunks.clear();
+                self.chunks.extend(self.tracker.psi_packets().cloned());
+                self.chunks.push(chunk.slice(offset..));
+                self.bytes = self.chunks.iter().map(|c| c.len()).sum();
+            }
+            None if !self.chunks.is_empty() => {
+                self.bytes += chunk.len();
+                self.chunks.push(chunk.clone());
+                if self.bytes > self.max_bytes {
+                    debug!("GOP 超过 {} 字节，放弃缓存", self.max_bytes);
+                    self.chunks.clear();
+                    self.bytes = 0;
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// 解析 host:port，IPv6 地址没有方括号时以最后一个冒号分隔端口
+fn parse_socket_addr(addr: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = addr.parse() {
+        return Some(addr);
+    }
+    let (ip, port) = addr.rsplit_once(':')?;
+    Some(SocketAddr::new(
+        IpAddr::V6(ip.parse().ok()?),
+        port.parse().ok()?,
+    ))
+}
+
+/// 解析组播地址：`239.1.1.1:5000`、`[ff3e::1]:5000`，SSM 为 `源地址@组地址:端口`
+pub(crate) fn parse_group_addr(addr: &str) -> Result<GroupAddr> {
+    let addr = ["igmp://", "rtp://", "udp://"]
+        .iter()
+        .fold(addr, |addr, scheme| addr.trim_start_matches(scheme))
+        .trim_start_matches('/');
+    let (source, group) = match addr.rsplit_once('@') {
+        Some((source, group)) => (Some(source), group),
+        None => (None, addr),
+    };
+
+    let group = parse_socket_addr(group).ok_or_else(|| anyhow!("无效的组播地址 {}", addr))?;
+    if !group.ip().is_multicast() {
+        return Err(anyhow!("{} 不是组播地址", group.ip()));
+    }
+    let source = match source {
+        Some(source) => {
+            let source: IpAddr = source
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .map_err(|e| anyhow!("无效的源地址 {}: {}", source, e))?;
+            if source.is_ipv4() != group.is_ipv4() {
+                return Err(anyhow!("源地址 {} 与组播地址 {} 的协议不同", source, group));
+            }
+            Some(source)
+        }
+        None => None,
+    };
+    Ok(GroupAddr { source, group })
+}
+
+/// 创建 socket 并在指定网卡上加入组播组
+///
+/// 带源地址时使用 SSM 加入（IPv4 为 IGMPv3，IPv6 为 MLDv2）。
+pub(crate) fn join_multicast(
+    addr: GroupAddr,
+    if_name: Option<&str>,
+    recv_buffer: usize,
+) -> Result<UdpSocket> {
+    let domain = if addr.group.is_ipv4() {
+        Domain::IPV4
+    } else {
+        Domain::IPV6
+    };
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.group.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    if let Err(e) = socket.set_recv_buffer_size(recv_buffer) {
+        debug!("设置接收缓冲区失败: {}", e);
+    }
+
+    // Linux 下绑定组播地址本身，避免收到同端口其他组的数据
+    #[cfg(unix)]
+    let bind_addr = addr.group;
+    #[cfg(not(unix))]
+    let bind_addr = match addr.group {
+        SocketAddr::V4(group) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), group.port()),
+        SocketAddr::V6(group) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), group.port()),
+    };
+    socket.bind(&bind_addr.into())?;
+
+    let if_name = if_name.filter(|i| !i.is_empty());
+    match (addr.group.ip(), addr.source) {
+        (IpAddr::V4(group), source) => {
+            let interface = match if_name {
+                Some(i) => interface_ipv4(i)?,
+                None => Ipv4Addr::UNSPECIFIED,
+            };
+            match source {
+                Some(IpAddr::V4(source)) => socket.join_ssm_v4(&source, &group, &interface)?,
+                _ => socket.join_multicast_v4(&group, &interface)?,
+            }
+            info!("加入组播组 {} (网卡地址 {})", addr, interface);
+        }
+        (IpAddr::V6(group), source) => {
+            let interface = match if_name {
+                Some(i) => interface_index(i)?,
+                None => 0,
+            };
+            match source {
+                Some(source) => join_ssm_v6(&socket, source, group, interface)?,
+                None => socket.join_multicast_v6(&group, interface)?,
+            }
+            info!("加入组播组 {} (网卡索引 {})", addr, interface);
+        }
+    }
+    socket.set_nonblocking(true)?;
+
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// IPv6 SSM 加入（MLDv2），socket2 没有提供，直接设置 MCAST_JOIN_SOURCE_GROUP
+#[cfg(target_os = "linux")]
+fn join_ssm_v6(socket: &Socket, source: IpAddr, group: Ipv6Addr, interface: u32) -> Result<()> {
+    use socket2::SockAddr;
+    use std::os::fd::AsRawFd;
+
+    // 对应 struct group_source_req
+    #[repr(C)]
+    struct GroupSourceReq {
+        interface: u32,
+        group: libc::sockaddr_storage,
+        source: libc::sockaddr_storage,
+    }
+
+    let req = GroupSourceReq {
+        interface,
+        group: SockAddr::from(SocketAddr::new(group.into(), 0)).as_storage(),
+        source: SockAddr::from(SocketAddr::new(source, 0)).as_storage(),
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::MCAST_JOIN_SOURCE_GROUP,
+            &req as *const GroupSourceReq as *const libc::c_void,
+            std::mem::size_of::<GroupSourceReq>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn join_ssm_v6(_socket: &Socket, _source: IpAddr, _group: Ipv6Addr, _interface: u32) -> Result<()> {
+    Err(anyhow!("当前系统不支持 IPv6 SSM"))
+}
+
+/// 加入新组播组前检查组数量和总码率上限
+///
+/// 新组的码率未知，按已加入组的平均码率估算。观看请求不计算后台检测、探测加入的组，
+/// 后台加入时计算所有组，达到上限时由调用方跳过，不占用观看的名额。
+fn check_limits(
+    groups: &HashMap<GroupAddr, Arc<Group>>,
+    config: &RelayConfig,
+    background: bool,
+) -> Result<()> {
+    let counted: Vec<&Arc<Group>> = groups
+        .values()
+        .filter(|g| background || !g.background.load(Ordering::Relaxed))
+        .collect();
+    if config.max_groups > 0 && counted.len() >= config.max_groups {
+        return Err(LimitExceeded(format!("已加入 {} 个组播组，达到上限", counted.len())).into());
+    }
+
+    if config.max_bitrate > 0 && !counted.is_empty() {
+        let total: u64 = counted
+            .iter()
+            .map(|g| g.bitrate.load(Ordering::Relaxed))
+            .sum::<u64>()
+            / 1000;
+        let estimated = total + total / counted.len() as u64;
+        if estimated > config.max_bitrate {
+            return Err(LimitExceeded(format!(
+                "当前总码率 {} kbps，再加入一个组播组将超过上限 {} kbps",
+                total, config.max_bitrate
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// 订阅组播组，组不存在时加入
+pub(crate) fn subscribe(
+    addr: GroupAddr,
+    if_name: Option<&str>,
+    config: &RelayConfig,
+) -> Result<Subscription> {
+    subscribe_group(addr, if_name, config, false)
+}
+
+/// 后台检测、探测订阅组播组：不占用观看的上限名额，达到上限时返回 LimitExceeded，
+/// 新加入的组在没有客户端后立即退出
+pub(crate) fn subscribe_background(
+    addr: GroupAddr,
+    if_name: Option<&str>,
+    config: &RelayConfig,
+) -> Result<Subscription> {
+    subscribe_group(addr, if_name, config, true)
+}
+
+fn subscribe_group(
+    addr: GroupAddr,
+    if_name: Option<&str>,
+    config: &RelayConfig,
+    background: bool,
+) -> Result<Subscription> {
+    let mut groups = GROUPS.lock().map_err(|_| anyhow!("组播组列表锁异常"))?;
+
+    if let Some(group) = groups.get(&addr) {
+        debug!("复用组播组 {}，当前客户端 {}", addr, group.sender.receiver_count());
+        if !background {
+            group.background.store(false, Ordering::Relaxed);
+        }
+        // 在 GOP 锁内订阅，保证缓存和实时数据之间不丢包也不重复
+        let gop = group.gop.lock().map_err(|_| anyhow!("GOP 缓存锁异常"))?;
+        return Ok(Subscription {
+            group: group.clone(),
+            cached: gop.chunks.clone(),
+            receiver: group.sender.subscribe(),
+        });
+    }
+
+    check_limits(&groups, config, background)?;
+    let socket = join_multicast(addr, if_name, config.recv_buffer)?;
+    let (sender, receiver) = broadcast::channel(config.buffer_packets.max(16));
+    let group = Arc::new(Group {
+        addr,
+        rtp_stats: RtpStats::default(),
+        bytes: AtomicU64::new(0),
+        bitrate: AtomicU64::new(0),
+        bitrate_history: Mutex::new(VecDeque::new()),
+        quality: Mutex::new(TsQuality::default()),
+        sender,
+        gop: Mutex::new(GopCache::new(config.gop_cache, config.gop_cache_max)),
+        background: AtomicBool::new(background),
+    });
+    groups.insert(addr, group.clone());
+
+    let idle_grace = Duration::from_secs(config.idle_grace);
+    let depacketizer = RtpDepacketizer::new(config.reorder_window);
+    tokio::spawn(receive_loop(group.clone(), socket, depacketizer, idle_grace));
+
+    Ok(Subscription {
+        group,
+        cached: Vec::new(),
+        receiver,
+    })
+}
+
+/// 接收组播数据并分发给所有客户端，空闲超过 idle_grace 后退出组播
+async fn receive_loop(
+    group: Arc<Group>,
+    socket: UdpSocket,
+    mut depacketizer: RtpDepacketizer,
+    idle_grace: Duration,
+) {
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+    let mut ready = Vec::new();
+    let mut idle_since: Option<Instant> = None;
+    let mut window_start = Instant::now();
+    let mut window_bytes = 0u64;
+    let mut history_start = Instant::now();
+    let mut history_bytes = 0u64;
+
+    loop {
+        if window_start.elapsed() >= BITRATE_WINDOW {
+            let bitrate =
+                window_bytes * 8 * 1000 / window_start.elapsed().as_millis().max(1) as u64;
+            group.bitrate.store(bitrate, Ordering::Relaxed);
+            window_start = Instant::now();
+            window_bytes = 0;
+        }
+        if history_start.elapsed() >= BITRATE_HISTORY_INTERVAL {
+            let kbps = history_bytes * 8 / history_start.elapsed().as_millis().max(1) as u64;
+            if let Ok(mut history) = group.bitrate_history.lock() {
+                history.push_back(kbps);
+                while history.len() > BITRATE_HISTORY_LEN {
+                    history.pop_front();
+                }
+            }
+            history_start = Instant::now();
+            history_bytes = 0;
+        }
+
+        // 有包在等待重排时，最多等到放弃等待的时间
+        let wait = depacketizer
+            .deadline()
+            .map_or(IDLE_CHECK_INTERVAL, |d| d.min(IDLE_CHECK_INTERVAL));
+        match tokio::time::timeout(wait, socket.recv(&mut buf)).await {
+            Ok(Ok(len)) => {
+                window_bytes += len as u64;
+                history_bytes += len as u64;
+                group.bytes.fetch_add(len as u64, Ordering::Relaxed);
+                let packet = Bytes::copy_from_slice(&buf[..len]);
+                depacketizer.push(packet, &group.rtp_stats, &mut ready);
+            }
+            Ok(Err(e)) => {
+                error!("组播 {} 接收失败: {}", group.addr, e);
+                tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+            }
+            // 组播中断时输出窗口中还在等待的包
+            Err(_) => depacketizer.flush_stalled(&group.rtp_stats, &mut ready),
+        }
+
+        if !ready.is_empty() {
+            if let Ok(mut quality) = group.quality.lock() {
+                let arrival = Instant::now();
+                for pkt in ready.iter().flat_map(|chunk| packets(chunk)) {
+                    quality.inspect(pkt, arrival);
+                }
+            }
+            if let Ok(mut gop) = group.gop.lock() {
+                for chunk in ready.drain(..) {
+                    gop.push(&chunk);
+                    // 没有客户端时 send 会返回错误，忽略即可
+                    let _ = group.sender.send(chunk);
+                }
+            }
+        }
+
+        if group.sender.receiver_count() > 0 {
+            idle_since = None;
+            continue;
+        }
+        let since = *idle_since.get_or_insert_with(Instant::now);
+        if since.elapsed() < idle_grace && !group.background.load(Ordering::Relaxed) {
+            continue;
+        }
+
+        // 在锁内再次确认没有新客户端，然后退出组播
+        if let Ok(mut groups) = GROUPS.lock() {
+            if group.sender.receiver_count() > 0 {
+                idle_since = None;
+                continue;
+            }
+            groups.remove(&group.addr);
+        }
+        info!("组播组 {} 空闲 {:?}，退出组播", group.addr, idle_grace);
+        info!(
+            "组播组 {} RTP 统计: 收到 {} 丢失 {} 重复 {} 乱序 {}",
+            group.addr,
+            group.rtp_stats.received.load(Ordering::Relaxed),
+            group.rtp_stats.lost.load(Ordering::Relaxed),
+            group.rtp_stats.duplicate.load(Ordering::Relaxed),
+            group.rtp_stats.reordered.load(Ordering::Relaxed),
+        );
+        if let Ok(quality) = group.quality.lock() {
+            info!(
+                "组播组 {} TS 统计: {} 个包，CC 错误 {}，PCR 不连续 {}，最大 PCR 抖动 {:.1} ms",
+                group.addr,
+                quality.packets,
+                quality.cc_errors,
+                quality.pcr_discontinuities,
+                quality.pcr_jitter_max_ms,
+            );
+        }
+        return;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(addr: &str, kbps: u64, background: bool) -> (GroupAddr, Arc<Group>) {
+        let addr = parse_group_addr(addr).unwrap();
+        let (sender, _) = broadcast::channel(16);
+        let group = Group {
+            addr,
+            rtp_stats: RtpStats::default(),
+            bytes: AtomicU64::new(0),
+            bitrate: AtomicU64::new(kbps * 1000),
+            bitrate_history: Mutex::new(VecDeque::new()),
+            quality: Mutex::new(TsQuality::default()),
+            sender,
+            gop: Mutex::new(GopCache::new(false, 0)),
+            background: AtomicBool::new(background),
+        };
+        (addr, Arc::new(group))
+    }
+
+    fn relay(max_groups: usize, max_bitrate: u64) -> RelayConfig {
+        RelayConfig {
+            max_groups,
+            max_bitrate,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ipv4_group() {
+        let addr = parse_group_addr("239.1.1.1:5000").unwrap();
+        assert_eq!(addr.source, None);
+        assert_eq!(addr.group, "239.1.1.1:5000".parse().unwrap());
+        assert_eq!(addr.to_string(), "239.1.1.1:5000");
+        assert!(parse_group_addr("10.0.0.1:5000").is_err());
+        assert!(parse_group_addr("239.1.1.1").is_err());
+    }
+
+    #[test]
+    fn ipv6_group() {
+        let addr = parse_group_addr("[ff3e::1]:5000").unwrap();
+        assert_eq!(addr.source, None);
+        assert_eq!(addr.group, "[ff3e::1]:5000".parse().unwrap());
+        assert_eq!(addr.to_string(), "[ff3e::1]:5000");
+        // 没有方括号时以最后一个冒号分隔端口
+        assert_eq!(parse_group_addr("ff3e::1:5000").unwrap(), addr);
+        assert!(parse_group_addr("[2001:db8::1]:5000").is_err());
+    }
+
+    #[test]
+    fn ssm_group() {
+        let addr = parse_group_addr("10.0.0.1@232.1.1.1:5000").unwrap();
+        assert_eq!(addr.source, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(addr.group, "232.1.1.1:5000".parse().unwrap());
+        assert_eq!(addr.to_string(), "10.0.0.1@232.1.1.1:5000");
+
+        let addr = parse_group_addr("[2001:db8::1]@[ff3e::1]:5000").unwrap();
+        assert_eq!(addr.source, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(addr.group, "[ff3e::1]:5000".parse().unwrap());
+        assert_eq!(addr.to_string(), "[2001:db8::1]@[ff3e::1]:5000");
+        assert_eq!(
+            parse_group_addr("2001:db8::1@[ff3e::1]:5000").unwrap(),
+            addr
+        );
+
+        assert!(parse_group_addr("abc@232.1.1.1:5000").is_err());
+    }
+
+    #[test]
+    fn mixed_address_family() {
+        assert!(parse_group_addr("2001:db8::1@232.1.1.1:5000").is_err());
+        assert!(parse_group_addr("[2001:db8::1]@232.1.1.1:5000").is_err());
+        assert!(parse_group_addr("10.0.0.1@[ff3e::1]:5000").is_err());
+    }
+
+    #[test]
+    fn scheme_normalization() {
+        let plain = parse_group_addr("10.0.0.1@232.1.1.1:5000").unwrap();
+        for addr in [
+            "igmp://10.0.0.1@232.1.1.1:5000",
+            "rtp://10.0.0.1@232.1.1.1:5000",
+            "udp://10.0.0.1@232.1.1.1:5000",
+            "rtp:///10.0.0.1@232.1.1.1:5000",
+        ] {
+            assert_eq!(parse_group_addr(addr).unwrap(), plain, "{}", addr);
+        }
+        let v6 = parse_group_addr("igmp://[ff3e::1]:5000").unwrap();
+        assert_eq!(format!("igmp://{}", v6), "igmp://[ff3e::1]:5000");
+        assert_eq!(parse_group_addr("rtp://[ff3e::1]:5000").unwrap(), v6);
+    }
+
+    #[test]
+    fn background_groups_not_counted_for_viewers() {
+        let groups: HashMap<_, _> = [
+            group("239.1.1.1:5000", 8000, false),
+            group("239.1.1.2:5000", 8000, true),
+        ]
+        .into_iter()
+        .collect();
+
+        assert!(check_limits(&groups, &relay(2, 0), false).is_ok());
+        // 后台加入时计算所有组
+        let e = check_limits(&groups, &relay(2, 0), true).unwrap_err();
+        assert!(e.is::<LimitExceeded>());
+        assert!(check_limits(&groups, &relay(1, 0), false).is_err());
+    }
+
+    #[test]
+    fn background_bitrate_not_counted_for_viewers() {
+        let groups: HashMap<_, _> = [
+            group("239.1.1.1:5000", 8000, false),
+            group("239.1.1.2:5000", 8000, true),
+        ]
+        .into_iter()
+        .collect();
+
+        // 观看：8000 + 8000 估算 16000
+        assert!(check_limits(&groups, &relay(0, 16000), false).is_ok());
+        // 后台：16000 + 8000 估算 24000
+        assert!(check_limits(&groups, &relay(0, 16000), true).is_err());
+        assert!(check_limits(&HashMap::new(), &relay(1, 1), true).is_ok());
+    }
+}
@@ -0,0 +1,319 @@
+// play.rs
+// 按频道播放：优先使用组播，组播中断时切换到 RTSP 直播地址，恢复后再切回组播
+// 带回看参数时转到 /catchup
+use actix_web::{
+    get,
+    http::header,
+    web::{Bytes, Data, Path, Query},
+    HttpRequest, HttpResponse, Responder,
+};
+use anyhow::Result;
+use futures_util::stream;
+use log::{debug, error, info, warn};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::RecvError, error::TryRecvError};
+
+use crate::catchup::CatchupQuery;
+use crate::config::{PlayMode, YamlConfig};
+use crate::health::is_dead;
+use crate::iptv::{find_channel, get_cached_channels, Channel};
+use crate::limits::{client_ip, limit_response};
+use crate::multicast::{parse_group_addr, subscribe, GroupAddr, Subscription};
+use crate::routes::base_url;
+use crate::rtsp::RtspPlayer;
+use crate::sessions::{open_session, tracked};
+use crate::sign::{check_signature, sign_url, strip_signature};
+use crate::utils::format_channel_name;
+
+/// 组播超过该时间没有数据，不再算作已恢复
+const RECOVER_GAP: Duration = Duration::from_secs(1);
+
+/// 一个客户端的播放状态
+struct Playback {
+    config: Data<YamlConfig>,
+    channel: Channel,
+    group: GroupAddr,
+    /// 切换数据源后先发送的数据（组播缓存的 GOP）
+    pending: VecDeque<Bytes>,
+    /// 组播订阅，使用 RTSP 时也保留，用来检测组播是否恢复
+    receiver: broadcast::Receiver<Bytes>,
+    /// 正在使用的 RTSP 直播，为 None 时使用组播
+    rtsp: Option<RtspPlayer>,
+    /// 组播恢复后开始持续有数据的时间
+    recovered_since: Option<Instant>,
+    last_multicast: Option<Instant>,
+}
+
+impl Playback {
+    async fn next(&mut self) -> Option<Bytes> {
+        let stall_timeout = Duration::from_secs(self.config.play.stall_timeout.max(1));
+        let timeout = Duration::from_secs(self.config.relay.timeout);
+
+        loop {
+            if let Some(chunk) = self.pending.pop_front() {
+                return Some(chunk);
+            }
+
+            let player = match self.rtsp.as_mut() {
+                Some(player) => player,
+                None => match tokio::time::timeout(stall_timeout, self.receiver.recv()).await {
+                    Ok(Ok(chunk)) => return Some(chunk),
+                    Ok(Err(RecvError::Lagged(skipped))) => {
+                        warn!("客户端跟不上组播 {}，跳过 {} 个包", self.group, skipped);
+                        continue;
+                    }
+                    Ok(Err(RecvError::Closed)) => return None,
+                    Err(_) => {
+                        warn!(
+                            "频道 {} 组播 {} 超过 {:?} 没有数据",
+                            self.channel.name, self.group, stall_timeout
+                        );
+                        if !self.switch_to_rtsp().await {
+                            return None;
+                        }
+                        continue;
+                    }
+                },
+            };
+
+            let chunk = match player.read(timeout).await {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    info!("频道 {} RTSP 结束: {}", self.channel.name, e);
+                    return None;
+                }
+            };
+            if self.multicast_recovered() {
+                self.switch_to_multicast();
+            }
+            return Some(chunk);
+        }
+    }
+
+    /// 切换到 RTSP 直播，不能切换时返回 false
+    async fn switch_to_rtsp(&mut self) -> bool {
+        if !self.config.play.fallback || self.channel.rtsp.is_empty() {
+            return false;
+        }
+        match RtspPlayer::open(
+            &self.channel.rtsp,
+            None,
+            self.config.iptv.interface(self.channel.account),
+        )
+        .await
+        {
+            Ok(player) => {
+                info!("频道 {} 切换到 RTSP 直播", self.channel.name);
+                self.rtsp = Some(player);
+                self.recovered_since = None;
+                self.last_multicast = None;
+                true
+            }
+            Err(e) => {
+                error!("频道 {} 打开 RTSP 失败: {}", self.channel.name, e);
+                false
+            }
+        }
+    }
+
+    /// 使用 RTSP 时检查组播是否已经持续有数据
+    fn multicast_recovered(&mut self) -> bool {
+        let mut received = false;
+        while let Ok(_) | Err(TryRecvError::Lagged(_)) = self.receiver.try_recv() {
+            received = true;
+        }
+
+        let now = Instant::now();
+        if received {
+            self.last_multicast = Some(now);
+            self.recovered_since.get_or_insert(now);
+        } else if self
+            .last_multicast
+            .is_none_or(|t| t.elapsed() > RECOVER_GAP)
+        {
+            self.recovered_since = None;
+        }
+
+        let recover_after = Duration::from_secs(self.config.play.recover_after);
+        self.recovered_since
+            .is_some_and(|t| t.elapsed() >= recover_after)
+    }
+
+    /// 切回组播，从组播缓存的 GOP 开始发送
+    fn switch_to_multicast(&mut self) {
+        let config = &self.config;
+        match subscribe(self.group, config.iptv.interface(self.channel.account), &config.relay) {
+            Ok(subscription) => {
+                info!("频道 {} 组播已恢复，切回组播", self.channel.name);
+                self.pending = subscription.cached.into();
+                self.receiver = subscription.receiver;
+                self.rtsp = None;
+            }
+            Err(e) => {
+                warn!("频道 {} 切回组播失败: {}", self.channel.name, e);
+                self.recovered_since = None;
+            }
+        }
+    }
+}
+
+/// 开始播放：订阅组播，已知组播失效时直接使用 RTSP
+async fn start(config: Data<YamlConfig>, channel: Channel) -> Result<Playback> {
+    let group = parse_group_addr(&channel.igmp)?;
+    let Subscription {
+        cached, receiver, ..
+    } = subscribe(group, config.iptv.interface(channel.account), &config.relay)?;
+
+    let dead = is_dead(channel.id);
+    let mut playback = Playback {
+        config,
+        channel,
+        group,
+        pending: cached.into(),
+        receiver,
+        rtsp: None,
+        recovered_since: None,
+        last_multicast: None,
+    };
+    if dead && playback.switch_to_rtsp().await {
+        playback.pending.clear();
+    }
+    Ok(playback)
+}
+
+/// 重定向模式下的地址：组播正常时使用组播转发地址，已知组播失效时使用 RTSP 直播地址
+fn redirect_url(config: &YamlConfig, channel: &Channel, base_url: &str) -> String {
+    if config.play.fallback && is_dead(channel.id) && !channel.rtsp.is_empty() {
+        return if config.m3u8.rtsp_proxy_uri.is_empty() {
+            channel.rtsp.clone()
+        } else {
+            channel
+                .rtsp
+                .replace("rtsp://", &format!("{}/rtsp/", config.m3u8.rtsp_proxy_uri))
+        };
+    }
+    let udp_proxy = if config.m3u8.udp_proxy_uri.is_empty() {
+        base_url
+    } else {
+        &config.m3u8.udp_proxy_uri
+    };
+    channel
+        .igmp
+        .replace("igmp://", &format!("{}/udp/", udp_proxy))
+}
+
+/// 播放一个频道：按配置重定向到当前地址或由本服务转发
+async fn play_channel(
+    config: Data<YamlConfig>,
+    channel: Channel,
+    req: &HttpRequest,
+) -> HttpResponse {
+    // 播放器按 append / shift 方式在直播地址后追加了回看参数
+    let catchup = Query::<CatchupQuery>::from_query(req.query_string())
+        .map(|query| query.has_time())
+        .unwrap_or(false);
+    if catchup {
+        // 原地址的签名只对 /play 有效，转到 /catchup 时重新签名
+        let url = format!(
+            "{}/catchup/{}?{}",
+            base_url(req),
+            channel.id,
+            strip_signature(req.query_string())
+        );
+        let url = sign_url(&config, &url, req);
+        return HttpResponse::Found()
+            .insert_header((header::LOCATION, url))
+            .finish();
+    }
+
+    if config.play.mode == PlayMode::Redirect {
+        let url = sign_url(
+            &config,
+            &redirect_url(&config, &channel, &base_url(req)),
+            req,
+        );
+        debug!("频道 {} 重定向到 {}", channel.name, url);
+        return HttpResponse::Found()
+            .insert_header((header::LOCATION, url))
+            .finish();
+    }
+
+    let session = open_session(
+        &config,
+        client_ip(req),
+        "play",
+        Some(channel.id),
+        channel.name.clone(),
+    );
+    let guard = match session {
+        Ok(guard) => guard,
+        Err(e) => {
+            return limit_response(&e)
+                .unwrap_or_else(|| HttpResponse::InternalServerError().body(e.to_string()))
+        }
+    };
+
+    let channel_name = channel.name.clone();
+    let playback = match start(config.clone(), channel).await {
+        Ok(playback) => playback,
+        Err(e) => {
+            if let Some(response) = limit_response(&e) {
+                return response;
+            }
+            error!("频道 {} 播放失败: {}", channel_name, e);
+            return HttpResponse::ServiceUnavailable().body(format!("播放失败: {}", e));
+        }
+    };
+
+    let body = stream::unfold(playback, |mut playback| async move {
+        let chunk = playback.next().await?;
+        Some((Ok::<_, actix_web::Error>(chunk), playback))
+    });
+
+    HttpResponse::Ok()
+        .content_type("video/mp2t")
+        .streaming(tracked(body, guard))
+}
+
+#[get("/play/{channel_id}")]
+pub async fn play(config: Data<YamlConfig>, path: Path<u64>, req: HttpRequest) -> impl Responder {
+    let channel_id = path.into_inner();
+    debug!("播放频道: {}", channel_id);
+    if let Some(response) = check_signature(&config, &req) {
+        return response;
+    }
+
+    match find_channel(&config.iptv, channel_id).await {
+        Ok(channel) => play_channel(config, channel, &req).await,
+        Err(e) => HttpResponse::NotFound().body(e.to_string()),
+    }
+}
+
+#[get("/play/by-name/{name}")]
+pub async fn play_by_name(
+    config: Data<YamlConfig>,
+    path: Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let name = path.into_inner();
+    debug!("按名称播放频道: {}", name);
+    if let Some(response) = check_signature(&config, &req) {
+        return response;
+    }
+
+    let channels = match get_cached_channels(&config.iptv).await {
+        Ok(channels) => channels,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    // 同时匹配原始名称和整理后的 tvg-name
+    let channel = channels.into_iter().find(|c| {
+        c.name == name
+            || format_channel_name(&c.name, config.name_mapping.as_ref(), &config.name_clean)
+                == name
+    });
+    match channel {
+        Some(channel) => play_channel(config, channel, &req).await,
+        None => HttpResponse::NotFound().body(format!("频道 {} 不存在", name)),
+    }
+}
@@ -0,0 +1,765 @@
+// probe.rs
+// 频道探测：从组播的 PAT/PMT/SDT 中读取编码、分辨率、音轨和服务名
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::YamlConfig;
+use crate::iptv::{get_cached_channels, Channel};
+use crate::limits::LimitExceeded;
+use crate::multicast::{parse_group_addr, subscribe_background, Subscription};
+use crate::ts::{
+    descriptors, is_video, packets, parse_pat, parse_pmt, parse_sdt, payload, payload_unit_start,
+    pid, psi_section, PAT_PID, SDT_PID,
+};
+
+/// 收集视频 PES 开头的最大包数，用于查找 SPS
+const MAX_PES_PACKETS: usize = 16;
+
+// channel_id -> 探测结果
+static PROBE_CACHE: Lazy<RwLock<HashMap<u64, StreamInfo>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VideoInfo {
+    pub codec: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioInfo {
+    pub codec: String,
+    pub language: Option<String>,
+}
+
+/// 一个频道的流信息
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamInfo {
+    pub service_name: Option<String>,
+    pub provider: Option<String>,
+    pub video: Option<VideoInfo>,
+    pub audio: Vec<AudioInfo>,
+    /// 探测时间（秒级时间戳）
+    pub probed_at: i64,
+}
+
+impl StreamInfo {
+    /// 按分辨率给出频道分组
+    pub fn group(&self) -> Option<&'static str> {
+        let height = self.video.as_ref()?.height?;
+        Some(if height >= 2160 {
+            "超清频道"
+        } else if height >= 720 {
+            "高清频道"
+        } else {
+            "普通频道"
+        })
+    }
+
+    /// 分辨率，例如 1920x1080
+    pub fn resolution(&self) -> Option<String> {
+        let video = self.video.as_ref()?;
+        Some(format!("{}x{}", video.width?, video.height?))
+    }
+}
+
+/// 获取缓存的探测结果
+pub(crate) fn cached_info(channel_id: u64) -> Option<StreamInfo> {
+    PROBE_CACHE.read().ok()?.get(&channel_id).cloned()
+}
+
+fn video_codec(stream_type: u8) -> &'static str {
+    match stream_type {
+        0x01 => "MPEG-1",
+        0x02 => "MPEG-2",
+        0x10 => "MPEG-4",
+        0x1b => "H.264",
+        0x24 => "H.265",
+        0x42 => "AVS",
+        0xd2 => "AVS2",
+        _ => "unknown",
+    }
+}
+
+/// 音频编码，0x06 私有流需要看描述符
+fn audio_codec(stream_type: u8, es_descriptors: &[u8]) -> Option<&'static str> {
+    match stream_type {
+        0x03 | 0x04 => Some("MP2"),
+        0x0f => Some("AAC"),
+        0x11 => Some("AAC-LATM"),
+        0x81 => Some("AC-3"),
+        0x87 => Some("E-AC-3"),
+        0x06 => descriptors(es_descriptors).find_map(|(tag, _)| match tag {
+            0x6a => Some("AC-3"),
+            0x7a => Some("E-AC-3"),
+            0x7c => Some("AAC"),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// ISO 639 语言描述符
+fn language(es_descriptors: &[u8]) -> Option<String> {
+    descriptors(es_descriptors)
+        .find(|(tag, body)| *tag == 0x0a && body.len() >= 3)
+        .map(|(_, body)| String::from_utf8_lossy(&body[..3]).into_owned())
+}
+
+/// 按位读取，支持指数哥伦布码
+struct BitReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl BitReader {
+    /// 去掉防竞争字节 00 00 03
+    fn new(nal: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(nal.len());
+        let mut zeros = 0;
+        for &b in nal {
+            if zeros >= 2 && b == 3 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if b == 0 { zeros + 1 } else { 0 };
+            data.push(b);
+        }
+        Self { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    fn bits(&mut self, n: usize) -> Option<u32> {
+        (0..n).try_fold(0u32, |acc, _| Some((acc << 1) | self.bit()?))
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.pos += n;
+        (self.pos <= self.data.len() * 8).then_some(())
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let v = self.ue()?;
+        Some(if v % 2 == 1 { v.div_ceil(2) as i32 } else { -((v / 2) as i32) })
+    }
+}
+
+/// 视频宽高的上限，超出时视为 SPS 损坏
+const MAX_DIMENSION: u32 = 16384;
+
+/// 减去两侧的裁剪量 (a + b) * unit，溢出或裁剪过多时返回 None
+fn cropped(size: u32, a: u32, b: u32, unit: u32) -> Option<u32> {
+    size.checked_sub(a.checked_add(b)?.checked_mul(unit)?)
+}
+
+/// 宽高都在 1..=MAX_DIMENSION 内时返回
+fn valid_size(width: u32, height: u32) -> Option<(u32, u32)> {
+    let range = 1..=MAX_DIMENSION;
+    (range.contains(&width) && range.contains(&height)).then_some((width, height))
+}
+
+/// 解析 H.264 SPS（不含 NAL 头），返回宽高
+fn parse_h264_sps(sps: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(sps);
+    let profile_idc = r.bits(8)?;
+    r.skip(16)?; // constraint flags + level_idc
+    r.ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    if matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135) {
+        chroma_format_idc = r.ue()?;
+        if chroma_format_idc == 3 {
+            r.skip(1)?;
+        }
+        r.ue()?; // bit_depth_luma
+        r.ue()?; // bit_depth_chroma
+        r.skip(1)?;
+        if r.bit()? == 1 {
+            let count = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..count {
+                if r.bit()? == 1 {
+                    let size = if i < 6 { 16 } else { 64 };
+                    let (mut last, mut next) = (8i32, 8i32);
+                    for _ in 0..size {
+                        if next != 0 {
+                            // delta_scale 的范围是 -128..=127
+                            let delta = r.se()?;
+                            if !(-128..=127).contains(&delta) {
+                                return None;
+                            }
+                            next = (last + delta + 256) % 256;
+                        }
+                        if next != 0 {
+                            last = next;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    r.ue()?; // log2_max_frame_num
+    match r.ue()? {
+        0 => {
+            r.ue()?;
+        }
+        1 => {
+            r.skip(1)?;
+            r.se()?;
+            r.se()?;
+            for _ in 0..r.ue()? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?; // max_num_ref_frames
+    r.skip(1)?;
+    let width_mbs = r.ue()?.checked_add(1)?;
+    let height_map_units = r.ue()?.checked_add(1)?;
+    let frame_mbs_only = r.bit()?;
+    if frame_mbs_only == 0 {
+        r.skip(1)?;
+    }
+    r.skip(1)?;
+
+    let mut width = width_mbs.checked_mul(16)?;
+    let mut height = height_map_units
+        .checked_mul(16)?
+        .checked_mul(2 - frame_mbs_only)?;
+    if r.bit()? == 1 {
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        let (crop_x, crop_y) = match chroma_format_idc {
+            0 => (1, 2 - frame_mbs_only),
+            1 => (2, 2 * (2 - frame_mbs_only)),
+            2 => (2, 2 - frame_mbs_only),
+            _ => (1, 2 - frame_mbs_only),
+        };
+        width = cropped(width, left, right, crop_x)?;
+        height = cropped(height, top, bottom, crop_y)?;
+    }
+    valid_size(width, height)
+}
+
+/// 解析 H.265 SPS（不含 NAL 头），返回宽高
+fn parse_hevc_sps(sps: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(sps);
+    r.skip(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = r.bits(3)? as usize;
+    r.skip(1)?;
+
+    // profile_tier_level
+    r.skip(88)?;
+    r.skip(8)?; // general_level_idc
+    let mut sub_layers = Vec::new();
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layers.push((r.bit()?, r.bit()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for (profile_present, level_present) in sub_layers {
+        if profile_present == 1 {
+            r.skip(88)?;
+        }
+        if level_present == 1 {
+            r.skip(8)?;
+        }
+    }
+
+    r.ue()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = r.ue()?;
+    if chroma_format_idc == 3 {
+        r.skip(1)?;
+    }
+    let mut width = r.ue()?;
+    let mut height = r.ue()?;
+    if r.bit()? == 1 {
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        let (sub_x, sub_y) = match chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        width = cropped(width, left, right, sub_x)?;
+        height = cropped(height, top, bottom, sub_y)?;
+    }
+    valid_size(width, height)
+}
+
+/// 从视频 PES 数据中找出分辨率
+fn parse_resolution(stream_type: u8, pes: &[u8]) -> Option<(u32, u32)> {
+    if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+        return None;
+    }
+    let es = pes.get(9 + pes[8] as usize..)?;
+
+    let starts: Vec<usize> = es
+        .windows(3)
+        .enumerate()
+        .filter(|(_, w)| *w == [0, 0, 1])
+        .map(|(i, _)| i + 3)
+        .collect();
+
+    starts.iter().enumerate().find_map(|(n, &start)| {
+        let end = starts.get(n + 1).map(|e| e - 3).unwrap_or(es.len());
+        let nal = es.get(start..end.max(start))?;
+        let header = *nal.first()?;
+        match stream_type {
+            0x1b if header & 0x1f == 7 => parse_h264_sps(&nal[1..]),
+            0x24 if (header >> 1) & 0x3f == 33 => parse_hevc_sps(nal.get(2..)?),
+            // MPEG-2 序列头: 12 位宽 + 12 位高
+            0x01 | 0x02 if header == 0xb3 && nal.len() >= 4 => {
+                let width = ((nal[1] as u32) << 4) | (nal[2] as u32 >> 4);
+                let height = (((nal[2] & 0x0f) as u32) << 8) | nal[3] as u32;
+                Some((width, height))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// 加入组播组读取一段时间，解析出流信息
+pub(crate) async fn probe_channel(config: &YamlConfig, channel: &Channel) -> Result<StreamInfo> {
+    let addr = parse_group_addr(&channel.igmp)?;
+    // 不占用观看的组播上限，达到上限时跳过
+    let Subscription {
+        cached,
+        mut receiver,
+        ..
+    } = subscribe_background(addr, config.iptv.interface(channel.account), &config.relay)?;
+    let mut cached = cached.into_iter();
+
+    let mut info = StreamInfo {
+        service_name: None,
+        provider: None,
+        video: None,
+        audio: Vec::new(),
+        probed_at: Utc::now().timestamp(),
+    };
+    let mut pmt_pid = None;
+    let mut video_stream = None;
+    let mut pmt_found = false;
+    let mut pes = Vec::new();
+    let mut pes_packets = 0;
+
+    let deadline = Instant::now() + Duration::from_secs(config.probe.timeout);
+    while Instant::now() < deadline {
+        let chunk = match cached.next() {
+            Some(chunk) => chunk,
+            None => match tokio::time::timeout_at(deadline.into(), receiver.recv()).await {
+                Ok(Ok(chunk)) => chunk,
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) | Err(_) => break,
+            },
+        };
+
+        for pkt in packets(&chunk) {
+            let pkt_pid = pid(pkt);
+            if pkt_pid == PAT_PID {
+                if let Some((_, p)) = psi_section(pkt).and_then(|s| parse_pat(s).first().copied()) {
+                    pmt_pid = Some(p);
+                }
+            } else if pkt_pid == SDT_PID && info.service_name.is_none() {
+                if let Some(service) = psi_section(pkt).and_then(parse_sdt) {
+                    info.service_name = Some(service.name);
+                    info.provider = Some(service.provider).filter(|p| !p.is_empty());
+                }
+            } else if Some(pkt_pid) == pmt_pid && !pmt_found {
+                let streams = psi_section(pkt).map(parse_pmt).unwrap_or_default();
+                if streams.is_empty() {
+                    continue;
+                }
+                pmt_found = true;
+                for es in streams {
+                    if is_video(es.stream_type) && video_stream.is_none() {
+                        video_stream = Some((es.pid, es.stream_type));
+                        info.video = Some(VideoInfo {
+                            codec: video_codec(es.stream_type).to_string(),
+                            width: None,
+                            height: None,
+                        });
+                    } else if let Some(codec) = audio_codec(es.stream_type, &es.descriptors) {
+                        info.audio.push(AudioInfo {
+                            codec: codec.to_string(),
+                            language: language(&es.descriptors),
+                        });
+                    }
+                }
+            } else if let Some((video_pid, stream_type)) = video_stream {
+                if pkt_pid != video_pid || info.video.as_ref().is_some_and(|v| v.height.is_some()) {
+                    continue;
+                }
+                // 收集 PES 开头的几个包，查找 SPS / 序列头
+                if payload_unit_start(pkt) {
+                    pes.clear();
+                    pes_packets = 0;
+                }
+                if pes_packets < MAX_PES_PACKETS {
+                    pes.extend_from_slice(payload(pkt).unwrap_or_default());
+                    pes_packets += 1;
+                    if let Some((width, height)) = parse_resolution(stream_type, &pes) {
+                        if let Some(video) = info.video.as_mut() {
+                            video.width = Some(width);
+                            video.height = Some(height);
+                        }
+                    }
+                }
+            }
+        }
+
+        let resolution_done = info.video.as_ref().is_none_or(|v| v.height.is_some());
+        if pmt_found && resolution_done && info.service_name.is_some() {
+            break;
+        }
+    }
+
+    if !pmt_found {
+        return Err(anyhow!("{} 没有收到 PMT", addr));
+    }
+    debug!("频道 {} 探测结果: {:?}", channel.name, info);
+
+    if let Ok(mut cache) = PROBE_CACHE.write() {
+        cache.insert(channel.id, info.clone());
+    }
+    Ok(info)
+}
+
+/// 后台定期探测所有频道
+pub(crate) async fn probe_loop(config: YamlConfig) {
+    if !config.probe.enabled {
+        return;
+    }
+    let delay = Duration::from_secs(config.probe.delay);
+    let interval = Duration::from_secs(config.probe.interval);
+
+    loop {
+        match get_cached_channels(&config.iptv).await {
+            Ok(channels) => {
+                info!("开始探测 {} 个频道", channels.len());
+                for channel in channels.iter() {
+                    match probe_channel(&config, channel).await {
+                        Ok(_) => {}
+                        Err(e) if e.is::<LimitExceeded>() => {
+                            debug!("组播已达上限，跳过探测频道 {}: {}", channel.name, e)
+                        }
+                        Err(e) => warn!("探测频道 {} 失败: {}", channel.name, e),
+                    }
+                    tokio::time::sleep(delay).await;
+                }
+                info!("频道探测完成");
+            }
+            Err(e) => warn!("探测前获取频道列表失败: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按位写入，用于构造 SPS
+    #[derive(Default)]
+    struct BitWriter {
+        data: Vec<u8>,
+        len: usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, n: usize, value: u32) -> &mut Self {
+            for i in (0..n).rev() {
+                if self.len.is_multiple_of(8) {
+                    self.data.push(0);
+                }
+                if (value >> i) & 1 == 1 {
+                    *self.data.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+                }
+                self.len += 1;
+            }
+            self
+        }
+
+        fn ue(&mut self, value: u32) -> &mut Self {
+            let v = value + 1;
+            let len = 32 - v.leading_zeros() as usize;
+            self.bits(len - 1, 0).bits(len, v)
+        }
+
+        /// rbsp_trailing_bits
+        fn finish(&mut self) -> Vec<u8> {
+            self.bits(1, 1);
+            while !self.len.is_multiple_of(8) {
+                self.bits(1, 0);
+            }
+            self.data.clone()
+        }
+    }
+
+    /// 1920x1080 High profile H.264 SPS，编码 1920x1088 后裁掉下方 8 行
+    fn h264_sps_1080p() -> Vec<u8> {
+        BitWriter::default()
+            .bits(8, 100) // profile_idc
+            .bits(8, 0)
+            .bits(8, 40) // level_idc
+            .ue(0) // seq_parameter_set_id
+            .ue(1) // chroma_format_idc
+            .ue(0)
+            .ue(0)
+            .bits(1, 0)
+            .bits(1, 0) // seq_scaling_matrix_present_flag
+            .ue(0) // log2_max_frame_num_minus4
+            .ue(0) // pic_order_cnt_type
+            .ue(2)
+            .ue(4) // max_num_ref_frames
+            .bits(1, 0)
+            .ue(119) // pic_width_in_mbs_minus1
+            .ue(67) // pic_height_in_map_units_minus1
+            .bits(1, 1) // frame_mbs_only_flag
+            .bits(1, 1)
+            .bits(1, 1) // frame_cropping_flag
+            .ue(0)
+            .ue(0)
+            .ue(0)
+            .ue(4)
+            .bits(1, 0) // vui_parameters_present_flag
+            .finish()
+    }
+
+    /// 1920x1080 Main profile H.265 SPS
+    fn hevc_sps_1080p(max_sub_layers_minus1: u32) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.bits(4, 0).bits(3, max_sub_layers_minus1).bits(1, 1);
+        // general profile_tier_level
+        w.bits(8, 0x01)
+            .bits(32, 0x6000_0000)
+            .bits(32, 0)
+            .bits(16, 0);
+        w.bits(8, 120);
+        for _ in 0..max_sub_layers_minus1 {
+            // sub_layer_profile_present_flag, sub_layer_level_present_flag
+            w.bits(1, 0).bits(1, 1);
+        }
+        if max_sub_layers_minus1 > 0 {
+            w.bits(2 * (8 - max_sub_layers_minus1 as usize), 0);
+        }
+        for _ in 0..max_sub_layers_minus1 {
+            w.bits(8, 90);
+        }
+        w.ue(0) // sps_seq_parameter_set_id
+            .ue(1) // chroma_format_idc
+            .ue(1920)
+            .ue(1088)
+            .bits(1, 1) // conformance_window_flag
+            .ue(0)
+            .ue(0)
+            .ue(0)
+            .ue(4)
+            .finish()
+    }
+
+    /// 只有 ES 数据的视频 PES
+    fn pes(es: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0, 0, 1, 0xe0, 0, 0, 0x80, 0, 0];
+        pes.extend_from_slice(es);
+        pes
+    }
+
+    #[test]
+    fn exp_golomb() {
+        let data = BitWriter::default().ue(0).ue(1).ue(2).ue(255).finish();
+        let mut r = BitReader::new(&data);
+        assert_eq!(r.ue(), Some(0));
+        assert_eq!(r.ue(), Some(1));
+        assert_eq!(r.ue(), Some(2));
+        assert_eq!(r.ue(), Some(255));
+
+        // se: 1 -> 1, 2 -> -1, 3 -> 2
+        let data = BitWriter::default().ue(1).ue(2).ue(3).finish();
+        let mut r = BitReader::new(&data);
+        assert_eq!(r.se(), Some(1));
+        assert_eq!(r.se(), Some(-1));
+        assert_eq!(r.se(), Some(2));
+    }
+
+    #[test]
+    fn emulation_prevention_bytes_are_removed() {
+        let mut r = BitReader::new(&[0x00, 0x00, 0x03, 0x01, 0xff]);
+        assert_eq!(r.bits(24), Some(0x000001));
+        assert_eq!(r.bits(8), Some(0xff));
+        assert_eq!(r.bit(), None);
+    }
+
+    #[test]
+    fn h264_sps_with_cropping() {
+        assert_eq!(parse_h264_sps(&h264_sps_1080p()), Some((1920, 1080)));
+    }
+
+    /// Baseline profile H.264 SPS，crop 为左右上下的裁剪量
+    fn h264_sd_sps(
+        width_mbs_minus1: u32,
+        height_mbs_minus1: u32,
+        crop: Option<(u32, u32, u32, u32)>,
+    ) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.bits(8, 66)
+            .bits(8, 0)
+            .bits(8, 30)
+            .ue(0)
+            .ue(0)
+            .ue(0)
+            .ue(2)
+            .ue(1)
+            .bits(1, 0)
+            .ue(width_mbs_minus1)
+            .ue(height_mbs_minus1)
+            .bits(1, 1)
+            .bits(1, 1);
+        match crop {
+            Some((left, right, top, bottom)) => {
+                w.bits(1, 1).ue(left).ue(right).ue(top).ue(bottom);
+            }
+            None => {
+                w.bits(1, 0);
+            }
+        }
+        w.bits(1, 0).finish()
+    }
+
+    #[test]
+    fn h264_baseline_sps() {
+        let sps = h264_sd_sps(44, 35, None);
+        assert_eq!(parse_h264_sps(&sps), Some((720, 576)));
+        let sps = h264_sd_sps(44, 35, Some((8, 8, 0, 0)));
+        assert_eq!(parse_h264_sps(&sps), Some((688, 576)));
+    }
+
+    #[test]
+    fn h264_sps_out_of_range() {
+        // 宽度乘 16 溢出
+        assert_eq!(parse_h264_sps(&h264_sd_sps(0x1000_0000, 35, None)), None);
+        // 超过 MAX_DIMENSION
+        assert_eq!(parse_h264_sps(&h264_sd_sps(2000, 35, None)), None);
+        // 裁剪量相加、相乘溢出，或比画面还大
+        let overflow = Some((0x8000_0000, 0x8000_0000, 0, 0));
+        assert_eq!(parse_h264_sps(&h264_sd_sps(44, 35, overflow)), None);
+        let overflow = Some((0x8000_0000, 0, 0, 0));
+        assert_eq!(parse_h264_sps(&h264_sd_sps(44, 35, overflow)), None);
+        let too_large = Some((200, 200, 0, 0));
+        assert_eq!(parse_h264_sps(&h264_sd_sps(44, 35, too_large)), None);
+        // 裁掉全部画面
+        let all = Some((180, 180, 0, 0));
+        assert_eq!(parse_h264_sps(&h264_sd_sps(44, 35, all)), None);
+    }
+
+    #[test]
+    fn h264_scaling_list_out_of_range() {
+        let sps = BitWriter::default()
+            .bits(8, 100)
+            .bits(8, 0)
+            .bits(8, 40)
+            .ue(0)
+            .ue(1)
+            .ue(0)
+            .ue(0)
+            .bits(1, 0)
+            .bits(1, 1) // seq_scaling_matrix_present_flag
+            .bits(1, 1) // seq_scaling_list_present_flag[0]
+            .ue(0xffff_fffe) // delta_scale = -2147483647
+            .finish();
+        assert_eq!(parse_h264_sps(&sps), None);
+    }
+
+    #[test]
+    fn hevc_sps_out_of_range() {
+        let mut w = BitWriter::default();
+        w.bits(4, 0).bits(3, 0).bits(1, 1);
+        w.bits(8, 0x01)
+            .bits(32, 0x6000_0000)
+            .bits(32, 0)
+            .bits(16, 0)
+            .bits(8, 120);
+        let sps = w
+            .ue(0)
+            .ue(1)
+            .ue(1920)
+            .ue(1088)
+            .bits(1, 1)
+            .ue(0x8000_0000)
+            .ue(0x8000_0000)
+            .ue(0)
+            .ue(0)
+            .finish();
+        assert_eq!(parse_hevc_sps(&sps), None);
+    }
+
+    #[test]
+    fn h264_sps_truncated() {
+        let sps = h264_sps_1080p();
+        assert_eq!(parse_h264_sps(&sps[..4]), None);
+    }
+
+    #[test]
+    fn hevc_sps_with_cropping() {
+        assert_eq!(parse_hevc_sps(&hevc_sps_1080p(0)), Some((1920, 1080)));
+        assert_eq!(parse_hevc_sps(&hevc_sps_1080p(1)), Some((1920, 1080)));
+    }
+
+    #[test]
+    fn resolution_from_pes() {
+        let mut es = vec![0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x67];
+        es.extend(h264_sps_1080p());
+        es.extend([0, 0, 1, 0x68, 0xeb]);
+        assert_eq!(parse_resolution(0x1b, &pes(&es)), Some((1920, 1080)));
+
+        let mut es = vec![0, 0, 1, 0x42, 0x01];
+        es.extend(hevc_sps_1080p(0));
+        assert_eq!(parse_resolution(0x24, &pes(&es)), Some((1920, 1080)));
+
+        // MPEG-2 序列头: 0x780 x 0x438
+        let es = [0, 0, 1, 0xb3, 0x78, 0x04, 0x38, 0x13];
+        assert_eq!(parse_resolution(0x02, &pes(&es)), Some((1920, 1080)));
+
+        assert_eq!(parse_resolution(0x1b, &pes(&[0, 0, 1, 0x65, 0x88])), None);
+        assert_eq!(parse_resolution(0x1b, &[0, 0, 0, 0xe0]), None);
+    }
+
+    #[test]
+    fn audio_codec_and_language() {
+        // ISO 639 语言描述符
+        let descriptors = [0x0a, 0x04, b'c', b'h', b'i', 0x00];
+        assert_eq!(language(&descriptors).as_deref(), Some("chi"));
+        assert_eq!(language(&[]), None);
+        assert_eq!(audio_codec(0x0f, &[]), Some("AAC"));
+        assert_eq!(
+            audio_codec(0x06, &[0x52, 0x01, 0x10, 0x6a, 0x00]),
+            Some("AC-3")
+        );
+        assert_eq!(audio_codec(0x06, &[]), None);
+        assert_eq!(audio_codec(0x1b, &[]), None);
+    }
+}
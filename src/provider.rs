@@ -0,0 +1,104 @@
+// provider.rs
+// IPTV 平台接口：登录、频道列表、节目单和台标，不同省份的平台各自实现，在 iptv.provider 中选择
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::{debug, warn};
+use reqwest::{Client, Response, StatusCode, Url};
+
+use crate::config::{IptvConfig, ProviderKind};
+use crate::iptv::{Channel, Program};
+use crate::utils::{interface_ipv4, interface_mac};
+
+mod guangdong;
+
+/// 上游认证失效（会话过期或被踢下线），调用方会作废登录会话后重试
+#[derive(Debug)]
+pub(crate) struct AuthExpired(pub(crate) String);
+
+impl std::fmt::Display for AuthExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "认证失效: {}", self.0)
+    }
+}
+
+impl std::error::Error for AuthExpired {}
+
+/// 一个 IPTV 平台
+#[async_trait]
+pub(crate) trait Provider: Send + Sync {
+    /// 登录，返回认证后的 client 和 EPG 服务器地址
+    async fn login(&self, args: &IptvConfig) -> Result<(Client, String)>;
+
+    /// 获取频道列表（不包含节目单）
+    async fn channels(&self, client: &Client, base_url: &str) -> Result<Vec<Channel>>;
+
+    /// 获取频道在 begin 到 end（毫秒时间戳）之间的节目单
+    async fn playbill(
+        &self,
+        client: &Client,
+        base_url: &str,
+        channel_id: u64,
+        begin: i64,
+        end: i64,
+    ) -> Result<Vec<Program>>;
+
+    /// 获取频道台标
+    async fn icon(&self, args: &IptvConfig, id: &str) -> Result<Vec<u8>>;
+}
+
+/// 按配置选择平台
+pub(crate) fn provider(args: &IptvConfig) -> &'static dyn Provider {
+    match args.provider {
+        ProviderKind::Guangdong => &guangdong::Guangdong,
+    }
+}
+
+/// 认证信息中的 IP 地址：优先使用配置的 ip，否则使用 interface 网卡当前的 IPv4 地址
+pub(crate) fn auth_ip(args: &IptvConfig) -> String {
+    if let Some(ip) = args.ip.as_deref().filter(|ip| !ip.is_empty()) {
+        return ip.to_string();
+    }
+    if let Some(interface) = args.interface.as_deref() {
+        match interface_ipv4(interface) {
+            Ok(ip) => {
+                debug!("使用网卡 {} 的 IP 地址 {}", interface, ip);
+                return ip.to_string();
+            }
+            Err(e) => warn!("获取认证用的 IP 地址失败: {}", e),
+        }
+    }
+    "0.0.0.0".to_string()
+}
+
+/// 认证信息中的 MAC 地址：优先使用配置的 mac，否则使用 interface 网卡的 MAC 地址
+pub(crate) fn auth_mac(args: &IptvConfig) -> Result<String> {
+    if !args.mac.is_empty() {
+        return Ok(args.mac.clone());
+    }
+    let interface = args.interface.as_deref().ok_or(anyhow!(
+        "没有配置 mac，也没有配置 interface 用于获取 MAC 地址"
+    ))?;
+    let mac = interface_mac(interface)?;
+    debug!("使用网卡 {} 的 MAC 地址 {}", interface, mac);
+    Ok(mac)
+}
+
+/// 检查 EPG 服务器的响应：返回 401/403 或被重定向到了其他页面（通常是登录页）时视为认证失效
+pub(crate) fn check_auth(url: &Url, response: Response) -> Result<Response> {
+    let redirected = response.url().path() != url.path();
+    if redirected
+        || matches!(
+            response.status(),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+        )
+    {
+        return Err(AuthExpired(format!(
+            "{} 返回 {} ({})",
+            url.path(),
+            response.status(),
+            response.url()
+        ))
+        .into());
+    }
+    Ok(response.error_for_status()?)
+}
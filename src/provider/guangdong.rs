@@ -0,0 +1,286 @@
+// guangdong.rs
+// 广东电信 IPTV（华为 EPG）：EDS 获取 EPG 地址，smcphone OAuth 登录
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use des::{
+    cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyInit},
+    TdesEde3,
+};
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use rand::Rng;
+use regex_lite::Regex;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{auth_ip, auth_mac, check_auth, Provider};
+use crate::config::IptvConfig;
+use crate::iptv::{get_client_with_if, Channel, Program};
+
+const EDS_URL: &str = "http://eds.iptv.gd.cn:8082/EDS/jsp/AuthenticationURL";
+
+pub(crate) struct Guangdong;
+
+#[derive(Deserialize)]
+struct AuthJson {
+    epgurl: String,
+}
+
+#[derive(Deserialize)]
+struct TokenJson {
+    #[serde(rename = "EncryToken")]
+    encry_token: String,
+}
+
+#[derive(Deserialize)]
+struct PlaybillList {
+    #[serde(rename = "playbillLites")]
+    list: Vec<Bill>,
+}
+
+#[derive(Deserialize)]
+struct Bill {
+    name: String,
+    #[serde(rename = "startTime")]
+    start_time: i64,
+    #[serde(rename = "endTime")]
+    end_time: i64,
+}
+
+/// EDS 失败后多久内排到其他地址之后再尝试
+const EDS_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// 每个 EDS 地址最近一次失败的时间
+static EDS_FAILURES: Lazy<Mutex<HashMap<String, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 只保留 scheme://host:port
+fn origin(url: &str) -> Result<String> {
+    let url = reqwest::Url::parse(url)?;
+    Ok(format!(
+        "{}://{}:{}",
+        url.scheme(),
+        url.host_str().ok_or(anyhow!("no host"))?,
+        url.port_or_known_default().ok_or(anyhow!("no port"))?,
+    ))
+}
+
+/// 按尝试顺序排列的 EDS 地址：配置顺序，最近失败过的排到最后（先失败的在前）
+fn eds_urls(args: &IptvConfig) -> Vec<&str> {
+    let mut urls: Vec<&str> = if args.eds_urls.is_empty() {
+        vec![EDS_URL]
+    } else {
+        args.eds_urls.iter().map(String::as_str).collect()
+    };
+    if let Ok(failures) = EDS_FAILURES.lock() {
+        urls.sort_by_key(|url| {
+            failures
+                .get(*url)
+                .filter(|t| t.elapsed() < EDS_COOLDOWN)
+                .copied()
+        });
+    }
+    urls
+}
+
+async fn query_eds(client: &Client, eds_url: &str, user: &str) -> Result<String> {
+    let params = [("Action", "Login"), ("return_type", "1"), ("UserID", user)];
+
+    let url = reqwest::Url::parse_with_params(eds_url, params)?;
+
+    let response = client.get(url).send().await?.error_for_status()?;
+
+    origin(response.json::<AuthJson>().await?.epgurl.as_str())
+}
+
+async fn get_base_url(client: &Client, args: &IptvConfig) -> Result<String> {
+    if let Some(epg_url) = args.epg_url.as_deref().filter(|u| !u.is_empty()) {
+        return origin(epg_url);
+    }
+
+    let mut last_error = anyhow!("没有可用的 EDS 地址");
+    for eds_url in eds_urls(args) {
+        match query_eds(client, eds_url, &args.user).await {
+            Ok(base_url) => {
+                if let Ok(mut failures) = EDS_FAILURES.lock() {
+                    failures.remove(eds_url);
+                }
+                debug!("Got base_url {base_url} from {eds_url}");
+                return Ok(base_url);
+            }
+            Err(e) => {
+                warn!("EDS {} 不可用: {}", eds_url, e);
+                if let Ok(mut failures) = EDS_FAILURES.lock() {
+                    failures.insert(eds_url.to_string(), Instant::now());
+                }
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error.context("所有 EDS 地址都不可用"))
+}
+
+#[async_trait]
+impl Provider for Guangdong {
+    async fn login(&self, args: &IptvConfig) -> Result<(Client, String)> {
+        let user = args.user.as_str();
+        let passwd = args.passwd.as_str();
+        let mac = auth_mac(args)?;
+        let imei = args.imei.as_deref().unwrap_or("default_imei");
+        let ip = auth_ip(args);
+
+        // 创建客户端
+        let client = get_client_with_if(args.interface.as_deref())?;
+
+        // 获取基础 URL
+        let base_url = get_base_url(&client, args).await?;
+
+        // 第一步：获取 token
+        let params = [
+            ("response_type", "EncryToken"),
+            ("client_id", "smcphone"),
+            ("userid", user),
+        ];
+        let url = reqwest::Url::parse_with_params(
+            format!("{base_url}/EPG/oauth/v2/authorize").as_str(),
+            params,
+        )?;
+        let response = client.get(url).send().await?.error_for_status()?;
+        let token = response.json::<TokenJson>().await?.encry_token;
+        debug!("Got token {token}");
+
+        // 第二步：生成认证信息
+        let enc = ecb::Encryptor::<TdesEde3>::new_from_slice(
+            &format!("{:X}", md5::compute(passwd.as_bytes())).as_bytes()[0..24],
+        );
+        let enc = match enc {
+            Ok(enc) => Ok(enc),
+            Err(e) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("Encrypt error {e}"),
+            )),
+        }?;
+
+        let data = format!(
+            "{}${token}${user}${imei}${ip}${mac}$$CTC",
+            rand::thread_rng().gen_range(0..10000000),
+        );
+        let auth = hex::encode_upper(enc.encrypt_padded_vec_mut::<Pkcs7>(data.as_bytes()));
+        debug!("Got auth {auth}");
+
+        // 第三步：获取访问令牌
+        let params = [
+            ("client_id", "smcphone"),
+            ("DeviceType", "deviceType"),
+            ("UserID", user),
+            ("DeviceVersion", "deviceVersion"),
+            ("userdomain", "2"),
+            ("datadomain", "3"),
+            ("accountType", "1"),
+            ("authinfo", auth.as_str()),
+            ("grant_type", "EncryToken"),
+        ];
+        let url = reqwest::Url::parse_with_params(
+            format!("{base_url}/EPG/oauth/v2/token").as_str(),
+            params,
+        )?;
+
+        let _response = client.get(url).send().await?.error_for_status()?;
+
+        Ok((client, base_url))
+    }
+
+    async fn channels(&self, client: &Client, base_url: &str) -> Result<Vec<Channel>> {
+        let url =
+            reqwest::Url::parse(format!("{}/EPG/jsp/getchannellistHWCTC.jsp", base_url).as_str())?;
+
+        let response = check_auth(&url, client.get(url.clone()).send().await?)?;
+        let res = response.text().await?;
+
+        let channel_pattern = Regex::new(
+            r#"(?m)Authentication.CTCSetConfig([^"]*)ChannelID="([^"]*)",ChannelName="([^"]*)",UserChannelID="([^"]*)",ChannelURL="([^|]*)\|([^"]*)",(.*?)TimeShiftURL="([^"]*)""#,
+        )?;
+
+        let mut channels = Vec::new();
+
+        for cap in channel_pattern.captures_iter(&res) {
+            let channel_id = cap[2].to_string();
+            let channel_name = cap[3]
+                .to_string()
+                .replace('＋', "+")
+                .replace([' ', '-'], "");
+            let user_channel_id = cap[4].to_string();
+            let igmp = cap[5].to_string();
+            let time_shift_url = cap[8].to_string();
+
+            let channel_id = channel_id
+                .parse::<u64>()
+                .unwrap_or_else(|_| channel_id.as_str().chars().map(|c| c as u64).sum());
+
+            debug!("Found channel: {} (ID: {})", channel_name, channel_id);
+
+            channels.push(Channel {
+                id: channel_id,
+                user_channel_id,
+                name: channel_name,
+                rtsp: time_shift_url,
+                igmp,
+                epg: Vec::new(),
+                account: 0,
+            });
+        }
+
+        info!("Got {} channel(s)", channels.len());
+        Ok(channels)
+    }
+
+    async fn playbill(
+        &self,
+        client: &Client,
+        base_url: &str,
+        channel_id: u64,
+        begin: i64,
+        end: i64,
+    ) -> Result<Vec<Program>> {
+        let params = [
+            ("channelId", format!("{}", channel_id)),
+            ("begin", format!("{}", begin)),
+            ("end", format!("{}", end)),
+        ];
+        let url = reqwest::Url::parse_with_params(
+            format!("{base_url}/EPG/jsp/iptvsnmv3/en/play/ajax/_ajax_getPlaybillList.jsp").as_str(),
+            params,
+        )?;
+
+        let response = check_auth(&url, client.get(url.clone()).send().await?)?;
+        let play_bill_list: PlaybillList = response.json().await?;
+
+        Ok(play_bill_list
+            .list
+            .into_iter()
+            .map(|bill| Program {
+                start: bill.start_time,
+                stop: bill.end_time,
+                title: bill.name.clone(),
+                desc: bill.name,
+            })
+            .collect())
+    }
+
+    async fn icon(&self, args: &IptvConfig, id: &str) -> Result<Vec<u8>> {
+        let client = get_client_with_if(args.interface.as_deref())?;
+
+        let base_url = get_base_url(&client, args).await?;
+
+        let url = reqwest::Url::parse(&format!(
+            "{base_url}/EPG/jsp/iptvsnmv3/en/list/images/channelIcon/{}.png",
+            id
+        ))?;
+
+        let response = client.get(url).send().await?.error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+}
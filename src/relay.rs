@@ -0,0 +1,118 @@
+// relay.rs
+// 内置组播转 HTTP，替代 udpxy / msd_lite
+use actix_web::{
+    get,
+    web::{Data, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use futures_util::{stream, StreamExt};
+use log::{debug, error, info, warn};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::{LagPolicy, YamlConfig};
+use crate::iptv::cached_channel;
+use crate::limits::{client_ip, limit_response};
+use crate::multicast::{parse_group_addr, subscribe, Subscription};
+use crate::sessions::{open_session, tracked};
+use crate::sign::check_signature;
+
+/// 把组播数据转成 HTTP 流，同一组播组的客户端共享一份数据
+///
+/// RTP 解封装在组播组内完成，客户端收到的都是 TS。
+fn relay_response(
+    config: &YamlConfig,
+    kind: &'static str,
+    addr: &str,
+    req: &HttpRequest,
+) -> HttpResponse {
+    if let Some(response) = check_signature(config, req) {
+        return response;
+    }
+
+    let group = match parse_group_addr(addr) {
+        Ok(group) => group,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let channel = cached_channel(|c| parse_group_addr(&c.igmp).is_ok_and(|a| a == group));
+    // 在提供该频道的账号的网卡上加入组播，不在频道列表中时使用第一个账号
+    let interface = config.iptv.interface(channel.as_ref().map_or(0, |c| c.account));
+    let session = open_session(
+        config,
+        client_ip(req),
+        kind,
+        channel.as_ref().map(|c| c.id),
+        channel.map(|c| c.name).unwrap_or_else(|| group.to_string()),
+    );
+    let guard = match session {
+        Ok(guard) => guard,
+        Err(e) => {
+            return limit_response(&e)
+                .unwrap_or_else(|| HttpResponse::InternalServerError().body(e.to_string()))
+        }
+    };
+
+    let Subscription {
+        group,
+        cached,
+        receiver,
+    } = match subscribe(group, interface, &config.relay) {
+        Ok(subscription) => subscription,
+        Err(e) => {
+            if let Some(response) = limit_response(&e) {
+                warn!("拒绝加入组播组 {}: {}", group, e);
+                return response;
+            }
+            error!("加入组播组 {} 失败: {}", group, e);
+            return HttpResponse::ServiceUnavailable().body(format!("加入组播组失败: {}", e));
+        }
+    };
+
+    let timeout = Duration::from_secs(config.relay.timeout);
+    let lag_policy = config.relay.lag_policy;
+    let live = stream::unfold(receiver, move |mut receiver| {
+        let group = group.clone();
+        async move {
+            loop {
+                let packet = match tokio::time::timeout(timeout, receiver.recv()).await {
+                    Ok(Ok(packet)) => packet,
+                    Ok(Err(RecvError::Lagged(skipped))) => {
+                        if lag_policy == LagPolicy::Drop {
+                            warn!("客户端跟不上组播 {}，断开", group.addr);
+                            return None;
+                        }
+                        warn!("客户端跟不上组播 {}，跳过 {} 个包", group.addr, skipped);
+                        continue;
+                    }
+                    Ok(Err(RecvError::Closed)) => return None,
+                    Err(_) => {
+                        info!("组播 {} 超过 {:?} 没有数据，断开", group.addr, timeout);
+                        return None;
+                    }
+                };
+
+                return Some((Ok::<_, actix_web::Error>(packet), receiver));
+            }
+        }
+    });
+
+    // 先发送缓存的 GOP，播放器可以立即开始解码
+    let body = stream::iter(cached.into_iter().map(Ok::<_, actix_web::Error>)).chain(live);
+
+    HttpResponse::Ok()
+        .content_type("video/mp2t")
+        .streaming(tracked(body, guard))
+}
+
+#[get("/udp/{addr}")]
+pub async fn udp(config: Data<YamlConfig>, path: Path<String>, req: HttpRequest) -> impl Responder {
+    debug!("UDP 转发: {}", path);
+    relay_response(&config, "udp", &path, &req)
+}
+
+#[get("/rtp/{addr}")]
+pub async fn rtp(config: Data<YamlConfig>, path: Path<String>, req: HttpRequest) -> impl Responder {
+    debug!("RTP 转发: {}", path);
+    relay_response(&config, "rtp", &path, &req)
+}
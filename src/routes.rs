@@ -13,7 +13,10 @@ use serde::{Deserialize, Serialize};
 use std::sync::Mutex;  // 添加这行
 // use xml::EventReader;  // 删除这行
 
-use crate::config::YamlConfig;
+use crate::config::{CatchupMode, PlayUrlStyle, YamlConfig};
+use crate::health::is_dead;
+use crate::probe::cached_info;
+use crate::sign::{check_access, sign_url};
 use crate::iptv::{get_channels, get_icon,  get_channels_epg, get_channel_date_epg, Channel};
 use crate::utils::{
     to_xmltv, parse_extra_xml, parse_extra_playlist, 
@@ -36,6 +39,14 @@ static ID_TO_TVGNAME: Lazy<Arc<RwLock<HashMap<u64, String>>>> = Lazy::new(|| {
 });
 
 
+// 播放列表查询参数
+#[derive(Debug, Deserialize)]
+pub struct PlaylistQuery {
+    pub style: Option<PlayUrlStyle>, // 覆盖配置中的直播地址形式
+    pub catchup: Option<CatchupMode>, // 覆盖配置中的回看方式
+    pub profile: Option<String>,      // 播放器配置名称，见 catchup.profiles
+}
+
 // 查询参数结构
 #[derive(Debug, Deserialize)]
 pub struct EpgQuery {
@@ -61,8 +72,11 @@ pub struct EpgItem {
 }
 
 #[get("/xmltv")]
-pub async fn xmltv(config: Data<YamlConfig>, _req: HttpRequest) -> impl Responder {
+pub async fn xmltv(config: Data<YamlConfig>, req: HttpRequest) -> impl Responder {
     debug!("Get EPG");
+    if let Some(response) = check_access(&config, &req) {
+        return response;
+    }
     
     // 获取额外的 XML 内容
     let extra_xml = match &config.m3u8.extra_xmltv {
@@ -165,13 +179,49 @@ pub fn get_channel_id_by_tvgname(tvgname: &str) -> Option<u64> {
 //         .unwrap_or_default()
 // }
 
+/// 客户端访问本服务使用的地址，例如 http://192.168.1.1:7878
+pub(crate) fn base_url(req: &HttpRequest) -> String {
+    let info = req.connection_info();
+    format!("{}://{}", info.scheme(), info.host())
+}
+
+/// 回看地址，带上 playseek 参数即可回放：开启本地时移的频道使用本服务的时移地址，否则使用 RTSP 地址
+pub(crate) fn catchup_url(config: &YamlConfig, channel: &Channel, base_url: &str) -> String {
+    if config.timeshift.includes(channel.id, &channel.name) {
+        format!("{}/timeshift/{}", base_url, channel.id)
+    } else if config.m3u8.rtsp_proxy_uri.is_empty() {
+        channel.rtsp.clone()
+    } else {
+        channel.rtsp.replace("rtsp://", &format!("{}/rtsp/", config.m3u8.rtsp_proxy_uri))
+    }
+}
+
 #[get("/playlist")]
-pub async fn playlist(config: Data<YamlConfig>, _req: HttpRequest) -> impl Responder {
+pub async fn playlist(
+    config: Data<YamlConfig>,
+    query: Query<PlaylistQuery>,
+    req: HttpRequest,
+) -> impl Responder {
     debug!("Get playlist");
+    if let Some(response) = check_access(&config, &req) {
+        return response;
+    }
+
+    let style = query.style.unwrap_or(config.m3u8.play_url_style);
+    let catchup_mode = query
+        .catchup
+        .or_else(|| {
+            query
+                .profile
+                .as_ref()
+                .and_then(|p| config.catchup.profiles.get(p).copied())
+        })
+        .unwrap_or(config.catchup.mode);
+    let base_url = base_url(&req);
     
     match get_channels(&config.iptv).await {
         Err(e) => {
-            info!("playlist: 获取失败 {}", config.iptv.user);
+            info!("playlist: 获取失败 {}", e);
             if let Some(old_playlist) = OLD_PLAYLIST.try_lock().ok().and_then(|f| f.to_owned()) {
                 HttpResponse::Ok()
                     .content_type("application/vnd.apple.mpegurl")
@@ -180,7 +230,12 @@ pub async fn playlist(config: Data<YamlConfig>, _req: HttpRequest) -> impl Respo
                 HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e))
             }
         }
-        Ok(ch) => {
+        Ok(mut ch) => {
+
+            // 隐藏存活检测失败的频道
+            if config.health.hide_dead {
+                ch.retain(|c| !is_dead(c.id));
+            }
 
             // 1. 创建本地映射
             let mut tvgname_to_id = HashMap::new();
@@ -218,13 +273,29 @@ pub async fn playlist(config: Data<YamlConfig>, _req: HttpRequest) -> impl Respo
                 + &ch
                     .into_iter()
                     .map(|c| {
-                        let group = if c.name.contains("超清") {
-                            "超清频道"
-                        } else if c.name.contains("高清") {
-                            "高清频道"
-                        } else {
-                            "普通频道"
+                        // 优先使用探测到的分辨率，没有探测结果时按名称猜测
+                        let stream_info = cached_info(c.id);
+                        let group = match stream_info.as_ref().and_then(|i| i.group()) {
+                            Some(group) => group,
+                            None if c.name.contains("超清") => "超清频道",
+                            None if c.name.contains("高清") => "高清频道",
+                            None => "普通频道",
                         };
+                        let stream_tags = stream_info
+                            .map(|i| {
+                                let mut tags = String::new();
+                                if let Some(video) = &i.video {
+                                    tags += &format!(r#" video-codec="{}""#, video.codec);
+                                }
+                                if let Some(resolution) = i.resolution() {
+                                    tags += &format!(r#" resolution="{}""#, resolution);
+                                }
+                                if let Some(audio) = i.audio.first() {
+                                    tags += &format!(r#" audio-codec="{}""#, audio.codec);
+                                }
+                                tags
+                            })
+                            .unwrap_or_default();
 
                         let tvgname = if config.m3u8.format_tvg {
                             format_channel_name(
@@ -238,39 +309,74 @@ pub async fn playlist(config: Data<YamlConfig>, _req: HttpRequest) -> impl Respo
 
                         let tvglogo = format!("https://live.fanmingming.com/tv/{}.png", tvgname);
                         
-                        let rtsp = if config.m3u8.rtsp_proxy_uri.is_empty() {
-                            c.rtsp 
-                        } else {
-                            c.rtsp.replace("rtsp://", &format!("{}/rtsp/", config.m3u8.rtsp_proxy_uri))
+                        let catch_up = match catchup_mode {
+                            CatchupMode::Default => {
+                                let rtsp = sign_url(&config, &catchup_url(&config, &c, &base_url), &req);
+                                let connector = if rtsp.contains('?') {
+                                    "&"
+                                } else {
+                                    "?"
+                                };
+                                format!(
+                                    r#" catchup="default" catchup-source="{}{}playseek=${{(b)yyyyMMddHHmmss}}-${{(e)yyyyMMddHHmmss}}" "#,
+                                    rtsp, connector
+                                )
+                            }
+                            // 直播地址不是 /play 时没有地方处理追加的回看参数，改为指向本服务的 /catchup
+                            CatchupMode::Append | CatchupMode::Shift
+                                if !matches!(style, PlayUrlStyle::Play | PlayUrlStyle::PlayByName) =>
+                            {
+                                let source = sign_url(&config, &format!("{}/catchup/{}", base_url, c.id), &req);
+                                let connector = if source.contains('?') { "&" } else { "?" };
+                                format!(
+                                    r#" catchup="default" catchup-source="{}{}utc={{utc}}&lutc={{lutc}}" "#,
+                                    source, connector
+                                )
+                            }
+                            // 播放器把回看参数追加到直播地址，由 /play 转到 /catchup
+                            // 签名后的直播地址已经带有查询参数
+                            CatchupMode::Append if config.sign.key.is_empty() => {
+                                r#" catchup="append" catchup-source="?utc={utc}&lutc={lutc}" "#.to_string()
+                            }
+                            CatchupMode::Append => {
+                                r#" catchup="append" catchup-source="&utc={utc}&lutc={lutc}" "#.to_string()
+                            }
+                            CatchupMode::Shift => r#" catchup="shift" "#.to_string(),
                         };
 
-                        let catch_up = {
-                            let connector = if rtsp.contains('?') {
-                                "&"
-                            } else {
-                                "?"
-                            };
-                            format!(
-                                r#" catchup="default" catchup-source="{}{}playseek=${{(b)yyyyMMddHHmmss}}-${{(e)yyyyMMddHHmmss}}" "#,
-                                rtsp, connector
-                            )
+                        let play_url = match style {
+                            PlayUrlStyle::Hls => format!("{}/hls/{}/index.m3u8", base_url, c.id),
+                            PlayUrlStyle::Play => format!("{}/play/{}", base_url, c.id),
+                            // Host 请求头无法组成地址时退回 /play/{id}
+                            PlayUrlStyle::PlayByName => {
+                                match reqwest::Url::parse(&format!("{}/play/by-name/", base_url)) {
+                                    Ok(mut url) => {
+                                        if let Ok(mut segments) = url.path_segments_mut() {
+                                            segments.pop_if_empty().push(&tvgname);
+                                        }
+                                        url.to_string()
+                                    }
+                                    Err(_) => format!("{}/play/{}", base_url, c.id),
+                                }
+                            }
+                            PlayUrlStyle::Multicast if config.m3u8.udp_proxy_uri.is_empty() => c.igmp,
+                            PlayUrlStyle::Multicast => {
+                                c.igmp.replace("igmp://", &format!("{}/udp/", config.m3u8.udp_proxy_uri))
+                            }
                         };
 
-                        let play_url = if config.m3u8.udp_proxy_uri.is_empty() {
-                            c.igmp
-                        } else {
-                            c.igmp.replace("igmp://", &format!("{}/udp/", config.m3u8.udp_proxy_uri))
-                        };
+                        let play_url = sign_url(&config, &play_url, &req);
 
                         format!(
-                            r#"#EXTINF:-1 tvg-id="{id}" tvg-name="{tvgname}" tvg-chno="{chno}" {catch_up} tvg-logo="{tvglogo}" group-title="{group}",{name}"#,
+                            r#"#EXTINF:-1 tvg-id="{id}" tvg-name="{tvgname}" tvg-chno="{chno}" {catch_up} tvg-logo="{tvglogo}" group-title="{group}"{stream_tags},{name}"#,
                             id = c.id,
                             chno = c.user_channel_id,
                             name = c.name,
                             group = group,
                             catch_up = catch_up,
                             tvglogo = tvglogo,
-                            tvgname = tvgname
+                            tvgname = tvgname,
+                            stream_tags = stream_tags
                         ) + "\n" + &play_url
                     })
                     .collect::<Vec<_>>()
@@ -349,6 +455,7 @@ pub async fn epg(
                 rtsp: "".to_string(),
                 igmp: "".to_string(),
                 epg: Vec::new(),
+                account: 0,
             }
         }
     };
@@ -0,0 +1,410 @@
+// rtp.rs
+// RTP 解封装：自动识别 RTP / 裸 TS，按序号重排并统计丢包；以及单播转发用的 RTP 封装
+use actix_web::web::Bytes;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::ts::TS_SYNC_BYTE;
+
+/// 序号跳变超过该值时认为是流重启，直接重新同步
+const RESYNC_THRESHOLD: i16 = 1000;
+
+/// 缺失的包等待超过该时间后放弃，输出窗口中已收到的包
+pub(crate) const REORDER_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// 记住最近放弃等待的序号个数，用于识别这些包迟到
+const SKIPPED_HISTORY: usize = 1024;
+
+/// 每个组播组的 RTP 统计
+#[derive(Debug, Default)]
+pub(crate) struct RtpStats {
+    /// 收到的 RTP 包数
+    pub(crate) received: AtomicU64,
+    /// 丢失的包数（包括放弃等待后才到达的包）
+    pub(crate) lost: AtomicU64,
+    /// 重复的包数
+    pub(crate) duplicate: AtomicU64,
+    /// 晚于后面的包到达、经过重排补上的包数
+    pub(crate) reordered: AtomicU64,
+}
+
+impl RtpStats {
+    fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+/// 去掉 RTP 头，返回负载部分
+pub(crate) fn strip_rtp_header(packet: &[u8]) -> Option<&[u8]> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 {
+        return None;
+    }
+    let has_padding = packet[0] & 0x20 != 0;
+    let has_extension = packet[0] & 0x10 != 0;
+    let csrc_count = (packet[0] & 0x0f) as usize;
+
+    let mut offset = 12 + csrc_count * 4;
+    if has_extension {
+        if packet.len() < offset + 4 {
+            return None;
+        }
+        let ext_len = u16::from_be_bytes([packet[offset + 2], packet[offset + 3]]) as usize;
+        offset += 4 + ext_len * 4;
+    }
+
+    let mut end = packet.len();
+    if has_padding {
+        end = end.checked_sub(packet[end - 1] as usize)?;
+    }
+    if offset > end {
+        return None;
+    }
+    Some(&packet[offset..end])
+}
+
+/// RTP 序号
+fn rtp_sequence(packet: &[u8]) -> u16 {
+    u16::from_be_bytes([packet[2], packet[3]])
+}
+
+/// 把组播包转换成干净的 TS 数据
+///
+/// 裸 TS 直接透传；RTP 包去头后在 `window` 个包的窗口内按序号重排，
+/// 窗口满了或缺失的包等待超过 REORDER_TIMEOUT 时放弃等待。
+pub(crate) struct RtpDepacketizer {
+    window: usize,
+    next: Option<u16>,
+    pending: BTreeMap<i16, (u16, Bytes)>,
+    /// 窗口中开始有包等待的时间
+    waiting_since: Option<Instant>,
+    /// 最近放弃等待的序号
+    skipped: VecDeque<u16>,
+}
+
+impl RtpDepacketizer {
+    pub(crate) fn new(window: usize) -> Self {
+        Self {
+            window,
+            next: None,
+            pending: BTreeMap::new(),
+            waiting_since: None,
+            skipped: VecDeque::new(),
+        }
+    }
+
+    /// 距离放弃等待缺失的包还有多久，窗口为空时返回 None
+    pub(crate) fn deadline(&self) -> Option<Duration> {
+        self.waiting_since
+            .map(|since| REORDER_TIMEOUT.saturating_sub(since.elapsed()))
+    }
+
+    /// 缺失的包等待超时后，按顺序输出窗口中的包
+    pub(crate) fn flush_stalled(&mut self, stats: &RtpStats, out: &mut Vec<Bytes>) {
+        self.flush_expired(Instant::now(), stats, out);
+    }
+
+    fn flush_expired(&mut self, now: Instant, stats: &RtpStats, out: &mut Vec<Bytes>) {
+        if self
+            .waiting_since
+            .is_some_and(|since| now.duration_since(since) >= REORDER_TIMEOUT)
+        {
+            while !self.pending.is_empty() {
+                self.skip_gap(stats, out);
+            }
+        }
+    }
+
+    /// 处理一个组播包，把可以输出的 TS 数据放进 out
+    pub(crate) fn push(&mut self, packet: Bytes, stats: &RtpStats, out: &mut Vec<Bytes>) {
+        if packet.first() == Some(&TS_SYNC_BYTE) {
+            out.push(packet);
+            return;
+        }
+        let payload = match strip_rtp_header(&packet) {
+            Some(payload) if !payload.is_empty() => packet.slice_ref(payload),
+            _ => return,
+        };
+        RtpStats::add(&stats.received, 1);
+
+        let seq = rtp_sequence(&packet);
+        self.flush_stalled(stats, out);
+        let next = match self.next {
+            Some(next) => next,
+            None => {
+                self.next = Some(seq.wrapping_add(1));
+                out.push(payload);
+                return;
+            }
+        };
+
+        let diff = seq.wrapping_sub(next) as i16;
+        if !(-RESYNC_THRESHOLD..=RESYNC_THRESHOLD).contains(&diff) {
+            // 流重启或序号跳变，丢弃窗口重新同步
+            self.flush(out);
+            self.next = Some(seq.wrapping_add(1));
+            out.push(payload);
+            return;
+        }
+        if diff < 0 {
+            // 已经放弃等待的包迟到了，已计入丢失
+            if let Some(pos) = self.skipped.iter().position(|s| *s == seq) {
+                self.skipped.remove(pos);
+            } else {
+                RtpStats::add(&stats.duplicate, 1);
+            }
+            return;
+        }
+        if diff == 0 {
+            // 后面的包已经先到了，这个包补上了缺口
+            if !self.pending.is_empty() {
+                RtpStats::add(&stats.reordered, 1);
+            }
+            out.push(payload);
+            self.next = Some(seq.wrapping_add(1));
+            self.rebase(1);
+            self.drain_ready(out);
+            return;
+        }
+
+        // 有缺口：先放进窗口等待缺失的包
+        if self.pending.contains_key(&diff) {
+            RtpStats::add(&stats.duplicate, 1);
+            return;
+        }
+        if self
+            .pending
+            .last_key_value()
+            .is_some_and(|(last, _)| *last > diff)
+        {
+            RtpStats::add(&stats.reordered, 1);
+        }
+        self.pending.insert(diff, (seq, payload));
+        self.waiting_since.get_or_insert_with(Instant::now);
+
+        // 窗口满了，放弃等待最早缺失的包
+        while self.pending.len() > self.window {
+            self.skip_gap(stats, out);
+        }
+    }
+
+    /// 放弃等待窗口前面缺失的包，输出窗口中第一个包及之后连续的包
+    fn skip_gap(&mut self, stats: &RtpStats, out: &mut Vec<Bytes>) {
+        let Some((gap, (seq, payload))) = self.pending.pop_first() else {
+            return;
+        };
+        RtpStats::add(&stats.lost, gap as u64);
+        for missing in 0..gap {
+            self.skipped
+                .push_back(seq.wrapping_sub((gap - missing) as u16));
+        }
+        while self.skipped.len() > SKIPPED_HISTORY {
+            self.skipped.pop_front();
+        }
+        out.push(payload);
+        self.next = Some(seq.wrapping_add(1));
+        self.rebase(gap + 1);
+        self.drain_ready(out);
+    }
+
+    /// 输出窗口中已经连续的包
+    fn drain_ready(&mut self, out: &mut Vec<Bytes>) {
+        let mut shift = 0;
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() != shift {
+                break;
+            }
+            let (seq, payload) = entry.remove();
+            out.push(payload);
+            self.next = Some(seq.wrapping_add(1));
+            shift += 1;
+        }
+        if shift > 0 {
+            self.rebase(shift);
+        }
+    }
+
+    /// 期望序号前进 shift 后，调整窗口中各包的相对位置
+    fn rebase(&mut self, shift: i16) {
+        if self.pending.is_empty() {
+            self.waiting_since = None;
+            return;
+        }
+        self.pending = std::mem::take(&mut self.pending)
+            .into_iter()
+            .map(|(diff, v)| (diff - shift, v))
+            .collect();
+        // 窗口前面出现新的缺口，重新开始计时
+        self.waiting_since = Some(Instant::now());
+    }
+
+    /// 按顺序输出窗口中剩余的包
+    fn flush(&mut self, out: &mut Vec<Bytes>) {
+        self.waiting_since = None;
+        self.skipped.clear();
+        out.extend(
+            std::mem::take(&mut self.pending)
+                .into_values()
+                .map(|(_, p)| p),
+        );
+    }
+}
+
+/// RTP 负载类型 33: MP2T
+const RTP_PAYLOAD_TYPE_MP2T: u8 = 33;
+
+/// 把 TS 封装成 RTP（RFC 2250），时间戳为 90kHz
+pub(crate) struct RtpPacketizer {
+    sequence: u16,
+    ssrc: u32,
+    start: Instant,
+}
+
+impl RtpPacketizer {
+    pub(crate) fn new() -> Self {
+        Self {
+            sequence: rand::random(),
+            ssrc: rand::random(),
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn packet(&mut self, payload: &[u8]) -> Vec<u8> {
+        let timestamp = (self.start.elapsed().as_micros() * 9 / 100) as u32;
+        let mut packet = Vec::with_capacity(12 + payload.len());
+        packet.push(0x80);
+        packet.push(RTP_PAYLOAD_TYPE_MP2T);
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        packet.extend_from_slice(payload);
+        self.sequence = self.sequence.wrapping_add(1);
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 负载为序号低字节的 RTP 包
+    fn rtp(seq: u16) -> Bytes {
+        let mut packet = vec![0x80, RTP_PAYLOAD_TYPE_MP2T];
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(&[0; 8]);
+        packet.push(seq as u8);
+        Bytes::from(packet)
+    }
+
+    fn push_all(depacketizer: &mut RtpDepacketizer, stats: &RtpStats, seqs: &[u16]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for seq in seqs {
+            depacketizer.push(rtp(*seq), stats, &mut out);
+        }
+        out.iter().map(|p| p[0]).collect()
+    }
+
+    fn counts(stats: &RtpStats) -> (u64, u64, u64, u64) {
+        (
+            stats.received.load(Ordering::Relaxed),
+            stats.lost.load(Ordering::Relaxed),
+            stats.duplicate.load(Ordering::Relaxed),
+            stats.reordered.load(Ordering::Relaxed),
+        )
+    }
+
+    #[test]
+    fn in_order() {
+        let stats = RtpStats::default();
+        let mut depacketizer = RtpDepacketizer::new(4);
+        let out = push_all(&mut depacketizer, &stats, &[1, 2, 3, 4, 5]);
+        assert_eq!(out, [1, 2, 3, 4, 5]);
+        assert_eq!(counts(&stats), (5, 0, 0, 0));
+        assert!(depacketizer.deadline().is_none());
+    }
+
+    #[test]
+    fn gap_never_filled() {
+        let stats = RtpStats::default();
+        let mut depacketizer = RtpDepacketizer::new(2);
+        // 3 一直没有到达，窗口满了之后放弃等待
+        let out = push_all(&mut depacketizer, &stats, &[1, 2, 4, 5, 6]);
+        assert_eq!(out, [1, 2, 4, 5, 6]);
+        assert_eq!(counts(&stats), (5, 1, 0, 0));
+    }
+
+    #[test]
+    fn late_fill() {
+        let stats = RtpStats::default();
+        let mut depacketizer = RtpDepacketizer::new(4);
+        let out = push_all(&mut depacketizer, &stats, &[1, 3, 4, 2, 5]);
+        assert_eq!(out, [1, 2, 3, 4, 5]);
+        assert_eq!(counts(&stats), (5, 0, 0, 1));
+    }
+
+    #[test]
+    fn late_fill_inside_window() {
+        let stats = RtpStats::default();
+        let mut depacketizer = RtpDepacketizer::new(4);
+        // 2、3 都缺失，3 比 4 晚到，2 最后补上
+        let out = push_all(&mut depacketizer, &stats, &[1, 4, 3, 2]);
+        assert_eq!(out, [1, 2, 3, 4]);
+        assert_eq!(counts(&stats), (4, 0, 0, 2));
+    }
+
+    #[test]
+    fn late_after_skip_is_not_duplicate() {
+        let stats = RtpStats::default();
+        let mut depacketizer = RtpDepacketizer::new(1);
+        let out = push_all(&mut depacketizer, &stats, &[1, 3, 4, 2]);
+        assert_eq!(out, [1, 3, 4]);
+        assert_eq!(counts(&stats), (4, 1, 0, 0));
+
+        // 真正重复的包
+        push_all(&mut depacketizer, &stats, &[4]);
+        assert_eq!(counts(&stats), (5, 1, 1, 0));
+    }
+
+    #[test]
+    fn wraparound() {
+        let stats = RtpStats::default();
+        let mut depacketizer = RtpDepacketizer::new(4);
+        let out = push_all(&mut depacketizer, &stats, &[65534, 65535, 0, 1]);
+        assert_eq!(out, [0xfe, 0xff, 0, 1]);
+        assert_eq!(counts(&stats), (4, 0, 0, 0));
+
+        let stats = RtpStats::default();
+        let mut depacketizer = RtpDepacketizer::new(4);
+        let out = push_all(&mut depacketizer, &stats, &[65534, 0, 65535, 1]);
+        assert_eq!(out, [0xfe, 0xff, 0, 1]);
+        assert_eq!(counts(&stats), (4, 0, 0, 1));
+    }
+
+    #[test]
+    fn flush_on_timeout() {
+        let stats = RtpStats::default();
+        let mut depacketizer = RtpDepacketizer::new(16);
+        let out = push_all(&mut depacketizer, &stats, &[1, 3, 4]);
+        assert_eq!(out, [1]);
+        assert!(depacketizer.deadline().is_some());
+
+        let mut out = Vec::new();
+        depacketizer.flush_expired(Instant::now(), &stats, &mut out);
+        assert!(out.is_empty());
+
+        depacketizer.flush_expired(Instant::now() + REORDER_TIMEOUT, &stats, &mut out);
+        assert_eq!(out.iter().map(|p| p[0]).collect::<Vec<_>>(), [3, 4]);
+        assert_eq!(counts(&stats), (3, 1, 0, 0));
+        assert!(depacketizer.deadline().is_none());
+    }
+
+    #[test]
+    fn raw_ts_passthrough() {
+        let stats = RtpStats::default();
+        let mut depacketizer = RtpDepacketizer::new(4);
+        let mut out = Vec::new();
+        depacketizer.push(Bytes::from_static(&[TS_SYNC_BYTE, 1, 2]), &stats, &mut out);
+        assert_eq!(out.len(), 1);
+        assert_eq!(counts(&stats), (0, 0, 0, 0));
+    }
+}
@@ -0,0 +1,563 @@
+// rtsp.rs
+// 内置 RTSP 转 HTTP，用于回看（TimeShiftURL），替代 rtp2httpd
+use actix_web::{
+    get,
+    web::{Bytes, Data, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use anyhow::{anyhow, Context, Result};
+use chrono::{FixedOffset, NaiveDateTime, TimeZone, Utc};
+use futures_util::stream;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{lookup_host, TcpSocket, TcpStream};
+
+use crate::config::YamlConfig;
+use crate::iptv::cached_channel;
+use crate::limits::{client_ip, limit_response};
+use crate::rtp::strip_rtp_header;
+use crate::sessions::{open_session, tracked};
+use crate::sign::{check_signature, strip_signature};
+use crate::ts::PcrClock;
+use crate::utils::{interface_ipv4, interface_ipv6};
+
+const USER_AGENT: &str = "iptv-proxy";
+/// DESCRIBE 最多跟随的重定向次数
+const MAX_REDIRECTS: usize = 5;
+/// 服务器没有给出 Session 超时时使用的默认值（秒），RFC 2326 规定为 60
+const DEFAULT_SESSION_TIMEOUT: u64 = 60;
+/// 上游 playseek 使用北京时间
+const PLAYSEEK_OFFSET: i32 = 8 * 60 * 60;
+/// 连接中断后连续重连的最大次数
+const MAX_RECONNECTS: u32 = 3;
+
+/// RTSP 响应
+struct RtspResponse {
+    status: u16,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+impl RtspResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(|s| s.as_str())
+    }
+}
+
+/// 简单的 RTSP 客户端，使用 TCP interleaved 方式接收 RTP
+pub(crate) struct RtspClient {
+    stream: BufReader<TcpStream>,
+    url: String,
+    cseq: u32,
+    session: Option<String>,
+    /// PLAY 使用的地址，保活请求也发到这里
+    control: String,
+    /// 保活间隔，为服务器 Session 超时的一半
+    keepalive: Duration,
+    last_keepalive: Instant,
+    /// 服务器不支持 GET_PARAMETER 时改用 OPTIONS 保活
+    keepalive_options: bool,
+}
+
+impl RtspClient {
+    /// 连接 RTSP 服务器，可选绑定到指定网卡
+    async fn connect(url: &str, if_name: Option<&str>) -> Result<Self> {
+        let parsed = reqwest::Url::parse(url)?;
+        let host = parsed.host_str().ok_or(anyhow!("no host"))?;
+        let port = parsed.port().unwrap_or(554);
+
+        // IPv6 地址在 URL 中带方括号
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        // 优先使用 IPv4 地址，按解析结果的地址族创建 socket
+        let addrs: Vec<SocketAddr> = lookup_host((host, port)).await?.collect();
+        let addr = addrs
+            .iter()
+            .find(|a| a.is_ipv4())
+            .or(addrs.first())
+            .copied()
+            .ok_or(anyhow!("无法解析 {}", host))?;
+
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        if let Some(i) = if_name.filter(|i| !i.is_empty()) {
+            let local = if addr.is_ipv4() {
+                interface_ipv4(i)?.into()
+            } else {
+                interface_ipv6(i)?.into()
+            };
+            socket.bind(SocketAddr::new(local, 0))?;
+        }
+        let stream = tokio::time::timeout(Duration::from_secs(5), socket.connect(addr))
+            .await
+            .context("连接 RTSP 服务器超时")??;
+
+        debug!("已连接 RTSP 服务器 {}", addr);
+        Ok(Self {
+            stream: BufReader::new(stream),
+            url: url.to_string(),
+            cseq: 0,
+            session: None,
+            control: url.to_string(),
+            keepalive: Duration::from_secs(DEFAULT_SESSION_TIMEOUT / 2),
+            last_keepalive: Instant::now(),
+            keepalive_options: false,
+        })
+    }
+
+    fn format_request(&mut self, method: &str, url: &str, headers: &[(&str, String)]) -> String {
+        self.cseq += 1;
+        let mut req = format!(
+            "{} {} RTSP/1.0\r\nCSeq: {}\r\nUser-Agent: {}\r\n",
+            method, url, self.cseq, USER_AGENT
+        );
+        if let Some(session) = &self.session {
+            req += &format!("Session: {}\r\n", session);
+        }
+        for (name, value) in headers {
+            req += &format!("{}: {}\r\n", name, value);
+        }
+        req += "\r\n";
+        req
+    }
+
+    /// 发送请求并读取响应
+    async fn request(
+        &mut self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, String)],
+    ) -> Result<RtspResponse> {
+        let req = self.format_request(method, url, headers);
+        debug!("RTSP 请求:\n{}", req);
+
+        self.stream.get_mut().write_all(req.as_bytes()).await?;
+        let response = tokio::time::timeout(Duration::from_secs(5), self.read_response())
+            .await
+            .context("RTSP 响应超时")??;
+        debug!("RTSP 响应: {} {:?}", response.status, response.headers);
+        Ok(response)
+    }
+
+    /// 读取一个 RTSP 响应，跳过中间夹杂的 interleaved 数据
+    async fn read_response(&mut self) -> Result<RtspResponse> {
+        loop {
+            let first = self.stream.read_u8().await?;
+            if first == b'$' {
+                let _channel = self.stream.read_u8().await?;
+                let len = self.stream.read_u16().await? as usize;
+                let mut skip = vec![0u8; len];
+                self.stream.read_exact(&mut skip).await?;
+                continue;
+            }
+
+            let mut status_line = String::from(first as char);
+            self.stream.read_line(&mut status_line).await?;
+            let status = status_line
+                .split_whitespace()
+                .nth(1)
+                .and_then(|s| s.parse::<u16>().ok())
+                .ok_or(anyhow!("无效的 RTSP 响应: {}", status_line.trim()))?;
+
+            let mut headers = HashMap::new();
+            loop {
+                let mut line = String::new();
+                if self.stream.read_line(&mut line).await? == 0 {
+                    return Err(anyhow!("RTSP 连接已关闭"));
+                }
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+                }
+            }
+
+            let mut body = String::new();
+            if let Some(len) = headers.get("content-length").and_then(|l| l.parse::<usize>().ok()) {
+                let mut buf = vec![0u8; len];
+                self.stream.read_exact(&mut buf).await?;
+                body = String::from_utf8_lossy(&buf).into_owned();
+            }
+
+            return Ok(RtspResponse { status, headers, body });
+        }
+    }
+
+    /// 完成 DESCRIBE / SETUP / PLAY，开始接收数据
+    pub(crate) async fn open(url: &str, range: Option<String>, if_name: Option<&str>) -> Result<Self> {
+        let mut client = Self::connect(url, if_name).await?;
+
+        // DESCRIBE，华为服务器通常会重定向到实际的媒体服务器
+        let mut redirects = 0;
+        let describe = loop {
+            let url = client.url.clone();
+            let res = client
+                .request("DESCRIBE", &url, &[("Accept", "application/sdp".to_string())])
+                .await?;
+            match res.status {
+                200 => break res,
+                301 | 302 | 303 | 305 | 307 if redirects < MAX_REDIRECTS => {
+                    let location = res.header("location").ok_or(anyhow!("重定向缺少 Location"))?;
+                    info!("RTSP 重定向到 {}", location);
+                    redirects += 1;
+                    client = Self::connect(location, if_name).await?;
+                }
+                status => return Err(anyhow!("DESCRIBE 失败: {}", status)),
+            }
+        };
+
+        let base = describe
+            .header("content-base")
+            .or(describe.header("content-location"))
+            .unwrap_or(&client.url)
+            .to_string();
+        let setup_url = sdp_control_url(&describe.body, &base);
+
+        // SETUP，使用 TCP interleaved
+        let res = client
+            .request(
+                "SETUP",
+                &setup_url,
+                &[("Transport", "RTP/AVP/TCP;unicast;interleaved=0-1".to_string())],
+            )
+            .await?;
+        if res.status != 200 {
+            return Err(anyhow!("SETUP 失败: {}", res.status));
+        }
+        let session = res.header("session").ok_or(anyhow!("SETUP 响应缺少 Session"))?;
+        client.session = Some(session.split(';').next().unwrap_or(session).trim().to_string());
+        // Session: 12345678;timeout=60
+        let timeout = session
+            .split(';')
+            .find_map(|p| p.trim().strip_prefix("timeout="))
+            .and_then(|t| t.trim().parse::<u64>().ok())
+            .unwrap_or(DEFAULT_SESSION_TIMEOUT);
+        client.keepalive = Duration::from_secs((timeout / 2).max(1));
+
+        // PLAY
+        let mut headers = Vec::new();
+        if let Some(range) = range {
+            headers.push(("Range", range));
+        }
+        let res = client.request("PLAY", &base, &headers).await?;
+        if res.status != 200 {
+            return Err(anyhow!("PLAY 失败: {}", res.status));
+        }
+
+        client.control = base;
+        client.last_keepalive = Instant::now();
+        info!("RTSP 开始播放 {}，保活间隔 {:?}", client.url, client.keepalive);
+        Ok(client)
+    }
+
+    /// 发送保活请求，不等待响应，响应由 read_payload 跳过
+    async fn send_keepalive(&mut self) -> Result<()> {
+        let method = if self.keepalive_options {
+            "OPTIONS"
+        } else {
+            "GET_PARAMETER"
+        };
+        let control = self.control.clone();
+        let req = self.format_request(method, &control, &[]);
+        debug!("RTSP 保活: {}", method);
+        self.stream.get_mut().write_all(req.as_bytes()).await?;
+        self.last_keepalive = Instant::now();
+        Ok(())
+    }
+
+    /// 读取下一段 TS 数据
+    pub(crate) async fn read_payload(&mut self) -> Result<Bytes> {
+        loop {
+            if self.session.is_some() && self.last_keepalive.elapsed() >= self.keepalive {
+                self.send_keepalive().await?;
+            }
+
+            let first = self.stream.read_u8().await?;
+            if first != b'$' {
+                // 保活响应或服务器发来的 RTSP 消息（例如 ANNOUNCE），读完头部后忽略
+                let mut line = String::from(first as char);
+                self.stream.read_line(&mut line).await?;
+                let status = line
+                    .strip_prefix("RTSP/")
+                    .and_then(|l| l.split_whitespace().nth(1))
+                    .and_then(|s| s.parse::<u16>().ok());
+                if matches!(status, Some(405 | 501)) && !self.keepalive_options {
+                    info!("RTSP 服务器不支持 GET_PARAMETER，改用 OPTIONS 保活");
+                    self.keepalive_options = true;
+                }
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    if self.stream.read_line(&mut header).await? == 0 {
+                        return Err(anyhow!("RTSP 连接已关闭"));
+                    }
+                    let header = header.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.trim().eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap_or(0);
+                        }
+                    }
+                }
+                let mut skip = vec![0u8; content_length];
+                self.stream.read_exact(&mut skip).await?;
+                continue;
+            }
+
+            let channel = self.stream.read_u8().await?;
+            let len = self.stream.read_u16().await? as usize;
+            let mut buf = vec![0u8; len];
+            self.stream.read_exact(&mut buf).await?;
+
+            // 只转发 RTP 通道的数据，RTCP 忽略
+            if channel != 0 {
+                continue;
+            }
+            let payload = match strip_rtp_header(&buf) {
+                Some(payload) => payload,
+                // 部分服务器直接发送 TS
+                None if buf.first() == Some(&0x47) => &buf[..],
+                None => continue,
+            };
+            if !payload.is_empty() {
+                return Ok(Bytes::copy_from_slice(payload));
+            }
+        }
+    }
+}
+
+/// 从 SDP 中找出 SETUP 使用的 URL
+fn sdp_control_url(sdp: &str, base: &str) -> String {
+    // 优先使用媒体级别的 control
+    let control = sdp
+        .lines()
+        .skip_while(|l| !l.starts_with("m="))
+        .find_map(|l| l.trim().strip_prefix("a=control:"))
+        .or_else(|| sdp.lines().find_map(|l| l.trim().strip_prefix("a=control:")));
+
+    match control {
+        Some(c) if c.starts_with("rtsp://") => c.to_string(),
+        Some(c) if c != "*" => format!("{}/{}", base.trim_end_matches('/'), c),
+        _ => base.to_string(),
+    }
+}
+
+/// 解析 playseek（北京时间 yyyyMMddHHmmss-yyyyMMddHHmmss），返回毫秒时间戳，结束时间可省略
+pub(crate) fn parse_playseek(playseek: &str) -> Result<(i64, Option<i64>)> {
+    let tz = FixedOffset::east_opt(PLAYSEEK_OFFSET).ok_or(anyhow!("invalid timezone"))?;
+    let to_millis = |s: &str| -> Result<i64> {
+        let local = NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M%S")
+            .with_context(|| format!("无法解析 playseek 时间 {}", s))?;
+        Ok(tz
+            .from_local_datetime(&local)
+            .single()
+            .ok_or(anyhow!("无效的时间 {}", s))?
+            .timestamp_millis())
+    };
+
+    let (begin, end) = playseek.split_once('-').unwrap_or((playseek, ""));
+    let end = if end.trim().is_empty() {
+        None
+    } else {
+        Some(to_millis(end.trim())?)
+    };
+    Ok((to_millis(begin.trim())?, end))
+}
+
+/// 用毫秒时间戳生成上游的 playseek（北京时间），结束时间可省略
+pub(crate) fn format_playseek(start: i64, stop: Option<i64>) -> Option<String> {
+    let tz = FixedOffset::east_opt(PLAYSEEK_OFFSET)?;
+    let to_local = |ms: i64| {
+        tz.timestamp_millis_opt(ms)
+            .single()
+            .map(|t| t.format("%Y%m%d%H%M%S").to_string())
+    };
+    let stop = match stop {
+        Some(stop) => to_local(stop)?,
+        None => String::new(),
+    };
+    Some(format!("{}-{}", to_local(start)?, stop))
+}
+
+/// 用毫秒时间戳生成 RTSP Range 头，时间使用 UTC，结束时间可省略
+pub(crate) fn clock_range(start: i64, stop: Option<i64>) -> Option<String> {
+    let to_clock = |ms: i64| {
+        Utc.timestamp_millis_opt(ms)
+            .single()
+            .map(|t| t.format("%Y%m%dT%H%M%SZ").to_string())
+    };
+    let stop = match stop {
+        Some(stop) => to_clock(stop)?,
+        None => String::new(),
+    };
+    Some(format!("clock={}-{}", to_clock(start)?, stop))
+}
+
+/// 带断线重连的 RTSP 播放
+///
+/// 连接中断时重新 PLAY，回看按 PCR 计算已播放的时长，从中断的位置继续。
+pub(crate) struct RtspPlayer {
+    pub(crate) url: String,
+    if_name: Option<String>,
+    /// 回看的起止时间（毫秒），直播时为 None
+    playseek: Option<(i64, Option<i64>)>,
+    client: RtspClient,
+    clock: PcrClock,
+    /// 本次连接开始时对应的回看位置（毫秒）
+    offset: i64,
+}
+
+impl RtspPlayer {
+    pub(crate) async fn open(
+        url: &str,
+        playseek: Option<(i64, Option<i64>)>,
+        if_name: Option<&str>,
+    ) -> Result<Self> {
+        let range = playseek.and_then(|(begin, end)| clock_range(begin, end));
+        let client = RtspClient::open(url, range, if_name).await?;
+        Ok(Self {
+            url: url.to_string(),
+            if_name: if_name.map(|i| i.to_string()),
+            playseek,
+            client,
+            clock: PcrClock::default(),
+            offset: playseek.map(|(begin, _)| begin).unwrap_or_default(),
+        })
+    }
+
+    /// 当前回看位置（毫秒）
+    fn position(&self) -> i64 {
+        self.offset + self.clock.millis()
+    }
+
+    /// 读取下一段 TS 数据，连接中断或超时后自动重连
+    pub(crate) async fn read(&mut self, timeout: Duration) -> Result<Bytes> {
+        let mut reconnects = 0;
+        loop {
+            let err = match tokio::time::timeout(timeout, self.client.read_payload()).await {
+                Ok(Ok(chunk)) => {
+                    self.clock.push(&chunk);
+                    return Ok(chunk);
+                }
+                Ok(Err(e)) => e,
+                Err(_) => anyhow!("超过 {:?} 没有数据", timeout),
+            };
+
+            // 回看已经播完，服务器正常关闭连接
+            if let Some((_, Some(end))) = self.playseek {
+                if self.position() >= end - 1000 {
+                    return Err(anyhow!("回看结束: {}", err));
+                }
+            }
+            if reconnects >= MAX_RECONNECTS {
+                return Err(err);
+            }
+            reconnects += 1;
+
+            let range = match self.playseek {
+                Some((_, end)) => clock_range(self.position(), end),
+                None => None,
+            };
+            warn!(
+                "RTSP {} 中断: {}，第 {} 次重连 {:?}",
+                self.url, err, reconnects, range
+            );
+            tokio::time::sleep(Duration::from_secs(reconnects as u64)).await;
+            match RtspClient::open(&self.url, range, self.if_name.as_deref()).await {
+                Ok(client) => {
+                    self.offset = self.position();
+                    self.clock = PcrClock::default();
+                    self.client = client;
+                }
+                Err(e) => warn!("RTSP {} 重连失败: {}", self.url, e),
+            }
+        }
+    }
+}
+
+/// 还原上游 RTSP 地址，并取出 playseek 参数
+fn upstream_url(path: &str, query: &str) -> (String, Option<String>) {
+    let mut playseek = None;
+    let params: Vec<&str> = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .filter(|p| match p.strip_prefix("playseek=") {
+            Some(v) => {
+                playseek = Some(v.to_string());
+                false
+            }
+            None => true,
+        })
+        .collect();
+
+    let mut url = format!("rtsp://{}", path);
+    if !params.is_empty() {
+        url = url + "?" + &params.join("&");
+    }
+    (url, playseek)
+}
+
+#[get("/rtsp/{url:.*}")]
+pub async fn rtsp(config: Data<YamlConfig>, path: Path<String>, req: HttpRequest) -> impl Responder {
+    if let Some(response) = check_signature(&config, &req) {
+        return response;
+    }
+    let (url, playseek) = upstream_url(&path, &strip_signature(req.query_string()));
+    debug!("RTSP 转发: {} playseek={:?}", url, playseek);
+
+    let playseek = match playseek.as_deref().map(parse_playseek).transpose() {
+        Ok(playseek) => playseek,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let base_url = url.split('?').next().unwrap_or(&url);
+    let channel = cached_channel(|c| c.rtsp.split('?').next() == Some(base_url));
+    let interface = config.iptv.interface(channel.as_ref().map_or(0, |c| c.account));
+    let session = open_session(
+        &config,
+        client_ip(&req),
+        "rtsp",
+        channel.as_ref().map(|c| c.id),
+        channel.map(|c| c.name).unwrap_or_else(|| url.clone()),
+    );
+    let guard = match session {
+        Ok(guard) => guard,
+        Err(e) => {
+            return limit_response(&e)
+                .unwrap_or_else(|| HttpResponse::InternalServerError().body(e.to_string()))
+        }
+    };
+
+    let player = match RtspPlayer::open(&url, playseek, interface).await {
+        Ok(player) => player,
+        Err(e) => {
+            error!("打开 RTSP {} 失败: {}", url, e);
+            return HttpResponse::BadGateway().body(format!("打开 RTSP 失败: {}", e));
+        }
+    };
+
+    let timeout = Duration::from_secs(config.relay.timeout);
+    let body = stream::unfold(player, move |mut player| async move {
+        match player.read(timeout).await {
+            Ok(chunk) => Some((Ok::<_, actix_web::Error>(chunk), player)),
+            Err(e) => {
+                info!("RTSP {} 结束: {}", player.url, e);
+                None
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("video/mp2t")
+        .streaming(tracked(body, guard))
+}
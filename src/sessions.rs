@@ -0,0 +1,360 @@
+// sessions.rs
+// 观看会话：记录每个客户端正在看的频道、流量和码率，可以主动断开，结束后写入观看记录
+use actix_web::{
+    delete, get,
+    web::{Bytes, Data, Path, Query},
+    HttpRequest, HttpResponse, Responder,
+};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures_util::{stream, FutureExt, Stream, StreamExt};
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+
+use crate::config::{SessionConfig, YamlConfig};
+use crate::limits::LimitExceeded;
+use crate::sign::check_local;
+
+/// 统计码率的时间窗口
+const BITRATE_WINDOW: Duration = Duration::from_secs(1);
+
+// session_id -> 正在进行的会话
+static SESSIONS: Lazy<Mutex<HashMap<u64, Arc<Session>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// 已结束会话的观看记录
+static HISTORY: Lazy<Mutex<VecDeque<HistoryEntry>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 待追加到观看记录文件的一行：(文件路径, JSON)
+type HistoryLine = (String, String);
+
+// 后台写入观看记录文件，会话结束时不在当前线程做文件 IO；不在 tokio 运行时中时为 None
+static HISTORY_WRITER: Lazy<Option<UnboundedSender<HistoryLine>>> = Lazy::new(|| {
+    let handle = tokio::runtime::Handle::try_current().ok()?;
+    let (sender, receiver) = unbounded_channel();
+    handle.spawn(history_writer(receiver));
+    Some(sender)
+});
+
+/// 一个正在观看的客户端
+pub(crate) struct Session {
+    id: u64,
+    client: IpAddr,
+    /// 转发方式: udp / rtp / rtsp / timeshift / hls
+    kind: &'static str,
+    channel_id: Option<u64>,
+    /// 频道名称，未知时为组播地址或 RTSP 地址
+    channel: String,
+    /// 开始时间（秒级时间戳）
+    start: i64,
+    bytes: AtomicU64,
+    /// 最近一个统计窗口的码率（bit/s）
+    bitrate: AtomicU64,
+    window: Mutex<(Instant, u64)>,
+    kill: Notify,
+}
+
+/// 会话信息，用于接口
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub client: String,
+    pub kind: String,
+    pub channel_id: Option<u64>,
+    pub channel: String,
+    pub start: i64,
+    pub bytes: u64,
+    /// 当前码率（kbps）
+    pub bitrate: u64,
+}
+
+/// 一条观看记录
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    pub client: String,
+    pub kind: String,
+    pub channel_id: Option<u64>,
+    pub channel: String,
+    pub start: i64,
+    pub stop: i64,
+    pub bytes: u64,
+}
+
+impl Session {
+    fn info(&self) -> SessionInfo {
+        // 长时间没有数据时码率视为 0
+        let stale = self
+            .window
+            .lock()
+            .map(|w| w.0.elapsed() > BITRATE_WINDOW * 3)
+            .unwrap_or(false);
+        SessionInfo {
+            id: self.id,
+            client: self.client.to_string(),
+            kind: self.kind.to_string(),
+            channel_id: self.channel_id,
+            channel: self.channel.clone(),
+            start: self.start,
+            bytes: self.bytes.load(Ordering::Relaxed),
+            bitrate: if stale {
+                0
+            } else {
+                self.bitrate.load(Ordering::Relaxed) / 1000
+            },
+        }
+    }
+
+    /// 记录发送给客户端的数据
+    fn record(&self, len: usize) {
+        self.bytes.fetch_add(len as u64, Ordering::Relaxed);
+        if let Ok(mut window) = self.window.lock() {
+            window.1 += len as u64;
+            let elapsed = window.0.elapsed();
+            if elapsed >= BITRATE_WINDOW {
+                let bitrate = window.1 * 8 * 1000 / elapsed.as_millis().max(1) as u64;
+                self.bitrate.store(bitrate, Ordering::Relaxed);
+                *window = (Instant::now(), 0);
+            }
+        }
+    }
+}
+
+/// 会话在流结束或客户端断开时释放，并写入观看记录
+pub(crate) struct SessionGuard {
+    session: Arc<Session>,
+    config: SessionConfig,
+}
+
+impl SessionGuard {
+    /// 记录发送给客户端的数据，用于没有经过 tracked 的响应（如 HLS 分片）
+    pub(crate) fn record(&self, len: usize) {
+        self.session.record(len);
+    }
+
+    /// 会话是否已被主动断开
+    pub(crate) fn killed(&self) -> bool {
+        self.session.kill.notified().now_or_never().is_some()
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        if let Ok(mut sessions) = SESSIONS.lock() {
+            sessions.remove(&self.session.id);
+        }
+
+        let info = self.session.info();
+        let entry = HistoryEntry {
+            client: info.client,
+            kind: info.kind,
+            channel_id: info.channel_id,
+            channel: info.channel,
+            start: info.start,
+            stop: Utc::now().timestamp(),
+            bytes: info.bytes,
+        };
+        info!(
+            "客户端 {} 结束观看 {}，时长 {} 秒，{} 字节",
+            entry.client,
+            entry.channel,
+            entry.stop - entry.start,
+            entry.bytes
+        );
+        write_history(&self.config, entry);
+    }
+}
+
+/// 保存观看记录，配置了文件时同时追加写入
+fn write_history(config: &SessionConfig, entry: HistoryEntry) {
+    if !config.history_file.is_empty() {
+        match serde_json::to_string(&entry) {
+            Ok(line) => {
+                let line = (config.history_file.clone(), line);
+                match HISTORY_WRITER.as_ref() {
+                    Some(writer) => {
+                        if let Err(e) = writer.send(line) {
+                            append_history(vec![e.0]);
+                        }
+                    }
+                    None => append_history(vec![line]),
+                }
+            }
+            Err(e) => error!("写入观看记录 {} 失败: {}", config.history_file, e),
+        }
+    }
+
+    if let Ok(mut history) = HISTORY.lock() {
+        history.push_back(entry);
+        while history.len() > config.history_size {
+            history.pop_front();
+        }
+    }
+}
+
+/// 取出队列中的观看记录，在阻塞线程中批量追加到文件
+async fn history_writer(mut receiver: UnboundedReceiver<HistoryLine>) {
+    while let Some(line) = receiver.recv().await {
+        let mut lines = vec![line];
+        while let Ok(line) = receiver.try_recv() {
+            lines.push(line);
+        }
+        if let Err(e) = tokio::task::spawn_blocking(move || append_history(lines)).await {
+            error!("写入观看记录失败: {}", e);
+        }
+    }
+}
+
+/// 把观看记录追加到文件，会阻塞当前线程
+fn append_history(lines: Vec<HistoryLine>) {
+    for (path, line) in lines {
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(e) = result {
+            error!("写入观看记录 {} 失败: {}", path, e);
+        }
+    }
+}
+
+/// 开始一个会话，客户端同时观看的流数量超过 max_streams 时返回 LimitExceeded
+pub(crate) fn open_session(
+    config: &YamlConfig,
+    client: IpAddr,
+    kind: &'static str,
+    channel_id: Option<u64>,
+    channel: String,
+) -> Result<SessionGuard> {
+    let mut sessions = SESSIONS.lock().map_err(|_| anyhow!("会话列表锁异常"))?;
+
+    let max_streams = config.relay.max_streams_per_client;
+    let count = sessions.values().filter(|s| s.client == client).count();
+    if max_streams > 0 && count >= max_streams {
+        warn!("客户端 {} 已有 {} 路流，拒绝新的请求", client, count);
+        return Err(LimitExceeded(format!(
+            "客户端 {} 同时观看的流数量已达上限 {}",
+            client, max_streams
+        ))
+        .into());
+    }
+
+    let session = Arc::new(Session {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        client,
+        kind,
+        channel_id,
+        channel,
+        start: Utc::now().timestamp(),
+        bytes: AtomicU64::new(0),
+        bitrate: AtomicU64::new(0),
+        window: Mutex::new((Instant::now(), 0)),
+        kill: Notify::new(),
+    });
+    info!(
+        "客户端 {} 开始观看 {} ({})",
+        client, session.channel, session.kind
+    );
+    sessions.insert(session.id, session.clone());
+
+    Ok(SessionGuard {
+        session,
+        config: config.session.clone(),
+    })
+}
+
+/// 把会话绑定到响应流上：统计发送的数据，会话被断开时结束流
+pub(crate) fn tracked<S, E>(body: S, guard: SessionGuard) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    stream::unfold((Box::pin(body), guard), |(mut body, guard)| async move {
+        let item = tokio::select! {
+            item = body.next() => item?,
+            _ = guard.session.kill.notified() => {
+                info!("会话 {} 已被断开", guard.session.id);
+                return None;
+            }
+        };
+        if let Ok(chunk) = &item {
+            guard.session.record(chunk.len());
+        }
+        Some((item, (body, guard)))
+    })
+}
+
+#[get("/api/sessions")]
+pub async fn list_sessions(config: Data<YamlConfig>, req: HttpRequest) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    let mut sessions: Vec<SessionInfo> = SESSIONS
+        .lock()
+        .map(|s| s.values().map(|s| s.info()).collect())
+        .unwrap_or_default();
+    sessions.sort_by_key(|s| s.id);
+    HttpResponse::Ok().json(sessions)
+}
+
+#[delete("/api/sessions/{id}")]
+pub async fn kill_session(
+    config: Data<YamlConfig>,
+    path: Path<u64>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    let id = path.into_inner();
+    let session = SESSIONS.lock().ok().and_then(|s| s.get(&id).cloned());
+    match session {
+        Some(session) => {
+            session.kill.notify_one();
+            HttpResponse::Ok().json(session.info())
+        }
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "会话不存在" })),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    /// 频道名称或 ID
+    pub channel: Option<String>,
+}
+
+#[get("/api/sessions/history")]
+pub async fn session_history(
+    config: Data<YamlConfig>,
+    query: Query<HistoryQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = check_local(&config, &req) {
+        return response;
+    }
+    let history: Vec<HistoryEntry> = HISTORY
+        .lock()
+        .map(|h| {
+            h.iter()
+                .rev()
+                .filter(|e| match &query.channel {
+                    Some(c) => {
+                        e.channel == *c || e.channel_id.is_some_and(|id| id.to_string() == *c)
+                    }
+                    None => true,
+                })
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+    HttpResponse::Ok().json(history)
+}
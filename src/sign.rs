@@ -0,0 +1,356 @@
+// sign.rs
+// 地址签名：播放列表中本服务的播放、转发和回看地址带上有效期和 HMAC 签名，拒绝没有有效签名的请求
+use actix_web::{web::Query, HttpRequest, HttpResponse};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::warn;
+use serde::Deserialize;
+use sha2::Sha256;
+use std::net::IpAddr;
+
+use crate::config::YamlConfig;
+use crate::limits::client_ip;
+use crate::routes::base_url;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const EXPIRES_PARAM: &str = "expires";
+const TOKEN_PARAM: &str = "token";
+
+#[derive(Debug, Deserialize)]
+struct SignQuery {
+    expires: Option<i64>,
+    token: Option<String>,
+    /// 访问播放列表和节目单用的密钥
+    key: Option<String>,
+}
+
+/// 签名对应的资源：HLS 的分片和 Flussonic 形式的回看地址共用 /hls/{channel_id}、/catchup/{channel_id} 的签名
+fn resource(path: &str) -> &str {
+    for prefix in ["/hls/", "/catchup/"] {
+        if let Some(pos) = path.strip_prefix(prefix).and_then(|rest| rest.find('/')) {
+            return &path[..prefix.len() + pos];
+        }
+    }
+    path
+}
+
+/// 开启 bind_client 时签名绑定客户端 IP
+fn bound_client(config: &YamlConfig, req: &HttpRequest) -> String {
+    if config.sign.bind_client {
+        client_ip(req).to_string()
+    } else {
+        String::new()
+    }
+}
+
+fn mac(key: &str, resource: &str, expires: i64, client: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC 可以使用任意长度的密钥");
+    mac.update(format!("{}\n{}\n{}", resource, expires, client).as_bytes());
+    mac
+}
+
+/// 给本服务的地址加上有效期和签名
+///
+/// 没有配置密钥，或地址不属于本服务（与请求的 scheme、Host 不同，如外部的 udpxy）时原样返回，
+/// 避免把签名泄露给其他服务器。
+pub(crate) fn sign_url(config: &YamlConfig, url: &str, req: &HttpRequest) -> String {
+    if config.sign.key.is_empty() {
+        return url.to_string();
+    }
+    let mut parsed = match reqwest::Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_string(),
+    };
+    let own =
+        reqwest::Url::parse(&base_url(req)).is_ok_and(|base| base.origin() == parsed.origin());
+    if !own {
+        return url.to_string();
+    }
+
+    let expires = Utc::now().timestamp() + config.sign.expire as i64;
+    let token = mac(
+        &config.sign.key,
+        resource(parsed.path()),
+        expires,
+        &bound_client(config, req),
+    )
+    .finalize()
+    .into_bytes();
+    parsed
+        .query_pairs_mut()
+        .append_pair(EXPIRES_PARAM, &expires.to_string())
+        .append_pair(TOKEN_PARAM, &hex::encode(token));
+    parsed.to_string()
+}
+
+/// 去掉查询参数中的签名，用于转发给上游或重新签名
+pub(crate) fn strip_signature(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .filter(|p| {
+            let name = p.split('=').next().unwrap_or(p);
+            name != EXPIRES_PARAM && name != TOKEN_PARAM
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn verify(config: &YamlConfig, req: &HttpRequest) -> Result<(), &'static str> {
+    let query = Query::<SignQuery>::from_query(req.query_string()).map_err(|_| "签名参数无效")?;
+    let (expires, token) = match (query.expires, query.token.as_deref()) {
+        (Some(expires), Some(token)) => (expires, token),
+        _ => return Err("缺少签名"),
+    };
+
+    let token = hex::decode(token).map_err(|_| "签名无效")?;
+    mac(
+        &config.sign.key,
+        resource(req.path()),
+        expires,
+        &bound_client(config, req),
+    )
+    .verify_slice(&token)
+    .map_err(|_| "签名无效")?;
+
+    if expires < Utc::now().timestamp() {
+        return Err("签名已过期");
+    }
+    Ok(())
+}
+
+/// 局域网地址：回环、私有地址、链路本地地址和 IPv6 ULA
+fn is_local(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || (ip.segments()[0] & 0xfe00) == 0xfc00
+                || (ip.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// 检查 /api 接口（状态查询、添加转发和录制、断开会话等）的请求，开启签名后只允许局域网地址访问，其他地址返回 403
+pub(crate) fn check_local(config: &YamlConfig, req: &HttpRequest) -> Option<HttpResponse> {
+    let ip = client_ip(req);
+    if config.sign.key.is_empty() || is_local(ip) {
+        return None;
+    }
+    warn!("拒绝 {} 的管理请求 {} {}", ip, req.method(), req.path());
+    Some(HttpResponse::Forbidden().body("管理接口只允许局域网访问"))
+}
+
+/// 常量时间比较，避免通过响应时间猜出密钥
+fn same_key(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// 检查播放列表和节目单的请求，开启签名后只允许局域网地址访问，或者在查询参数中带上 key=签名密钥，其他请求返回 403
+pub(crate) fn check_access(config: &YamlConfig, req: &HttpRequest) -> Option<HttpResponse> {
+    let ip = client_ip(req);
+    if config.sign.key.is_empty() || is_local(ip) {
+        return None;
+    }
+    let key = Query::<SignQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|q| q.into_inner().key);
+    if key.is_some_and(|key| same_key(key.as_bytes(), config.sign.key.as_bytes())) {
+        return None;
+    }
+    warn!("拒绝 {} 的请求 {}: 缺少访问密钥", ip, req.path());
+    Some(HttpResponse::Forbidden().body("局域网外访问需要 key 参数"))
+}
+
+/// 检查请求的签名，没有配置密钥时不检查；签名缺失、伪造或过期时返回 403
+pub(crate) fn check_signature(config: &YamlConfig, req: &HttpRequest) -> Option<HttpResponse> {
+    if config.sign.key.is_empty() {
+        return None;
+    }
+    let reason = verify(config, req).err()?;
+    warn!("拒绝 {} 的请求 {}: {}", client_ip(req), req.path(), reason);
+    Some(HttpResponse::Forbidden().body(reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    const HOST: &str = "192.168.1.1:7878";
+
+    fn config(bind_client: bool) -> YamlConfig {
+        serde_yaml::from_str(&format!(
+            r#"
+server: {{}}
+iptv:
+  user: "user"
+  passwd: "passwd"
+  mac: "FF:FF:FF:FF:FF:FF"
+m3u8: {{}}
+sign:
+  key: "secret"
+  bind_client: {}
+"#,
+            bind_client
+        ))
+        .unwrap()
+    }
+
+    /// 请求本服务 path?query 的客户端
+    fn request(uri: &str, peer: &str) -> HttpRequest {
+        TestRequest::default()
+            .uri(uri)
+            .insert_header(("Host", HOST))
+            .peer_addr(peer.parse().unwrap())
+            .to_http_request()
+    }
+
+    /// 签名后的地址中的 path?query
+    fn signed(config: &YamlConfig, path: &str, peer: &str) -> (String, String) {
+        let url = sign_url(
+            config,
+            &format!("http://{}{}", HOST, path),
+            &request("/playlist", peer),
+        );
+        let url = reqwest::Url::parse(&url).unwrap();
+        (
+            url.path().to_string(),
+            url.query().unwrap_or_default().to_string(),
+        )
+    }
+
+    #[test]
+    fn valid_token() {
+        let config = config(false);
+        let (path, query) = signed(&config, "/play/1", "10.0.0.2:1000");
+        assert!(query.contains("expires=") && query.contains("token="));
+
+        let req = request(&format!("{}?{}", path, query), "10.0.0.3:2000");
+        assert!(check_signature(&config, &req).is_none());
+    }
+
+    #[test]
+    fn missing_token() {
+        let config = config(false);
+        let req = request("/play/1", "10.0.0.2:1000");
+        assert!(check_signature(&config, &req).is_some());
+    }
+
+    #[test]
+    fn expired_token() {
+        let config = config(false);
+        let expires = Utc::now().timestamp() - 10;
+        let token = mac("secret", "/play/1", expires, "")
+            .finalize()
+            .into_bytes();
+        let req = request(
+            &format!("/play/1?expires={}&token={}", expires, hex::encode(token)),
+            "10.0.0.2:1000",
+        );
+        assert_eq!(verify(&config, &req), Err("签名已过期"));
+    }
+
+    #[test]
+    fn tampered_path() {
+        let config = config(false);
+        let (_, query) = signed(&config, "/play/1", "10.0.0.2:1000");
+        let req = request(&format!("/play/2?{}", query), "10.0.0.2:1000");
+        assert_eq!(verify(&config, &req), Err("签名无效"));
+    }
+
+    #[test]
+    fn tampered_expires() {
+        let config = config(false);
+        let (path, query) = signed(&config, "/play/1", "10.0.0.2:1000");
+        let query = query.replacen("expires=", "expires=9", 1);
+        let req = request(&format!("{}?{}", path, query), "10.0.0.2:1000");
+        assert_eq!(verify(&config, &req), Err("签名无效"));
+    }
+
+    #[test]
+    fn hls_segment_under_signed_playlist() {
+        let config = config(false);
+        let (_, query) = signed(&config, "/hls/5/index.m3u8", "10.0.0.2:1000");
+
+        let req = request(&format!("/hls/5/segment_42.ts?{}", query), "10.0.0.2:1000");
+        assert!(check_signature(&config, &req).is_none());
+
+        let req = request(&format!("/hls/6/segment_42.ts?{}", query), "10.0.0.2:1000");
+        assert!(check_signature(&config, &req).is_some());
+    }
+
+    #[test]
+    fn bound_to_client() {
+        let config = config(true);
+        let (path, query) = signed(&config, "/play/1", "10.0.0.2:1000");
+
+        let req = request(&format!("{}?{}", path, query), "10.0.0.2:2000");
+        assert!(check_signature(&config, &req).is_none());
+
+        let req = request(&format!("{}?{}", path, query), "10.0.0.3:2000");
+        assert!(check_signature(&config, &req).is_some());
+    }
+
+    #[test]
+    fn foreign_urls_are_not_signed() {
+        let config = config(false);
+        let req = request("/playlist", "10.0.0.2:1000");
+        for url in [
+            "http://192.168.1.2:4022/udp/239.1.1.1:5000",
+            "https://192.168.1.1:7878/play/1",
+            "igmp://239.1.1.1:5000",
+        ] {
+            assert_eq!(sign_url(&config, url, &req), url);
+        }
+    }
+
+    #[test]
+    fn strip_signature_keeps_other_params() {
+        assert_eq!(
+            strip_signature("playseek=1-2&expires=3&token=abc&utc=4"),
+            "playseek=1-2&utc=4"
+        );
+    }
+
+    #[test]
+    fn local_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "192.168.1.2",
+            "10.1.2.3",
+            "::1",
+            "fd00::1",
+            "::ffff:192.168.1.2",
+        ] {
+            assert!(is_local(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["8.8.8.8", "2001:db8::1", "::ffff:8.8.8.8"] {
+            assert!(!is_local(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn playlist_access() {
+        let config = config(false);
+        assert!(check_access(&config, &request("/playlist", "192.168.1.2:1000")).is_none());
+        assert!(check_access(&config, &request("/playlist", "8.8.8.8:1000")).is_some());
+        assert!(check_access(&config, &request("/playlist?key=secret", "8.8.8.8:1000")).is_none());
+        assert!(check_access(&config, &request("/xmltv?key=secrets", "8.8.8.8:1000")).is_some());
+        assert!(check_access(&config, &request("/xmltv?key=", "8.8.8.8:1000")).is_some());
+    }
+
+    #[test]
+    fn read_api_local_only() {
+        let config = config(false);
+        assert!(check_local(&config, &request("/api/sessions", "10.0.0.2:1000")).is_none());
+        assert!(check_local(&config, &request("/api/sessions", "8.8.8.8:1000")).is_some());
+        // 管理接口不接受 key 参数
+        assert!(check_local(
+            &config,
+            &request("/api/sessions?key=secret", "8.8.8.8:1000")
+        )
+        .is_some());
+    }
+}
@@ -0,0 +1,531 @@
+// timeshift.rs
+// 本地时移：把配置的频道持续写入磁盘上的环形缓冲区，按 playseek 回放
+use actix_web::{
+    get,
+    web::{Bytes, Data, Path, Query},
+    HttpRequest, HttpResponse, Responder,
+};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures_util::stream;
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::YamlConfig;
+use crate::iptv::{cached_channel, get_cached_channels, Channel, CHANNEL_CACHE_DURATION};
+use crate::limits::{client_ip, limit_response};
+use crate::multicast::{parse_group_addr, subscribe};
+use crate::rtsp::parse_playseek;
+use crate::sessions::{open_session, tracked};
+use crate::sign::check_signature;
+use crate::ts::{packets, parse_pat, pid, psi_section, TsTracker, PAT_PID, TS_PACKET_SIZE};
+
+/// 组播中断后重新加入的间隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// 回放追上直播后等待新分块的轮询间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// 每次从文件读取的大小
+const READ_SIZE: usize = 64 * 1024;
+/// 分块开头 PAT/PMT 最多读取的包数
+const PSI_HEAD_PACKETS: usize = 16;
+
+// channel_id -> 已写完的分块，按时间排序
+static BUFFERS: Lazy<RwLock<HashMap<u64, VecDeque<Chunk>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// 一个已写完的分块文件，时间为毫秒时间戳
+#[derive(Debug, Clone)]
+struct Chunk {
+    start: i64,
+    stop: i64,
+    path: PathBuf,
+}
+
+/// 正在写入的分块
+struct ChunkWriter {
+    start: i64,
+    started: Instant,
+    path: PathBuf,
+    file: File,
+}
+
+fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+fn channel_dir(config: &YamlConfig, channel_id: u64) -> PathBuf {
+    PathBuf::from(&config.timeshift.dir).join(channel_id.to_string())
+}
+
+/// 载入磁盘上已有的分块，文件名为 {start}_{stop}.ts，未写完的 .part 文件直接删除
+fn load_chunks(dir: &std::path::Path) -> VecDeque<Chunk> {
+    let mut chunks: Vec<Chunk> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = path.file_name()?.to_str()?;
+            if name.ends_with(".part") {
+                let _ = std::fs::remove_file(&path);
+                return None;
+            }
+            let (start, stop) = name.strip_suffix(".ts")?.split_once('_')?;
+            Some(Chunk {
+                start: start.parse().ok()?,
+                stop: stop.parse().ok()?,
+                path,
+            })
+        })
+        .collect();
+    chunks.sort_by_key(|c| c.start);
+    chunks.into()
+}
+
+/// 保存写完的分块，并删除超出缓冲时长的旧分块
+fn push_chunk(config: &YamlConfig, channel_id: u64, chunk: Chunk) {
+    let expire = now_millis() - config.timeshift.hours as i64 * 3_600_000;
+    if let Ok(mut buffers) = BUFFERS.write() {
+        let chunks = buffers.entry(channel_id).or_default();
+        chunks.push_back(chunk);
+        while chunks.front().is_some_and(|c| c.stop < expire) {
+            if let Some(old) = chunks.pop_front() {
+                if let Err(e) = std::fs::remove_file(&old.path) {
+                    debug!("删除时移分块 {:?} 失败: {}", old.path, e);
+                }
+            }
+        }
+    }
+}
+
+/// 找到 cursor 之后、结束时间晚于 begin 的第一个分块
+fn next_chunk(channel_id: u64, cursor: i64, begin: i64) -> Option<Chunk> {
+    BUFFERS
+        .read()
+        .ok()?
+        .get(&channel_id)?
+        .iter()
+        .find(|c| c.start > cursor && c.stop > begin)
+        .cloned()
+}
+
+impl ChunkWriter {
+    async fn create(dir: &std::path::Path, tracker: &TsTracker) -> Result<Self> {
+        let start = now_millis();
+        let path = dir.join(format!("{}.part", start));
+        let mut file = File::create(&path).await?;
+        // 每个分块以 PAT/PMT 开头，可以单独解码
+        for psi in tracker.psi_packets() {
+            file.write_all(psi).await?;
+        }
+        Ok(Self {
+            start,
+            started: Instant::now(),
+            path,
+            file,
+        })
+    }
+
+    async fn finish(mut self, config: &YamlConfig, channel_id: u64) -> Result<()> {
+        self.file.flush().await?;
+        let stop = now_millis();
+        let path = self
+            .path
+            .with_file_name(format!("{}_{}.ts", self.start, stop));
+        tokio::fs::rename(&self.path, &path).await?;
+        push_chunk(
+            config,
+            channel_id,
+            Chunk {
+                start: self.start,
+                stop,
+                path,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// 持续把组播写入分块文件，组播中断时返回
+async fn capture(config: &YamlConfig, channel: &Channel, dir: &std::path::Path) -> Result<()> {
+    let addr = parse_group_addr(&channel.igmp)?;
+    // 只写实时数据，分块时间和内容保持一致
+    let mut receiver = subscribe(addr, config.iptv.interface(channel.account), &config.relay)?.receiver;
+    let timeout = Duration::from_secs(config.relay.timeout);
+    let target = Duration::from_secs(config.timeshift.chunk_duration.max(1));
+
+    let mut tracker = TsTracker::default();
+    let mut writer: Option<ChunkWriter> = None;
+    let mut pending: Vec<u8> = Vec::new();
+
+    let result = loop {
+        let chunk = match tokio::time::timeout(timeout, receiver.recv()).await {
+            Ok(Ok(chunk)) => chunk,
+            Ok(Err(RecvError::Lagged(skipped))) => {
+                warn!("时移频道 {} 跳过 {} 个包", channel.name, skipped);
+                continue;
+            }
+            Ok(Err(RecvError::Closed)) => break Err(anyhow!("组播已关闭")),
+            Err(_) => break Err(anyhow!("组播 {} 超过 {:?} 没有数据", addr, timeout)),
+        };
+
+        for pkt in packets(&chunk) {
+            let keyframe = tracker.inspect(pkt);
+            // 到达目标时长后在关键帧处切分；长时间没有关键帧时强制切分
+            let cut = match &writer {
+                Some(w) => {
+                    (keyframe && w.started.elapsed() >= target) || w.started.elapsed() >= target * 3
+                }
+                None => keyframe && tracker.ready(),
+            };
+            if cut {
+                if let Some(mut w) = writer.take() {
+                    w.file.write_all(&pending).await?;
+                    pending.clear();
+                    w.finish(config, channel.id).await?;
+                }
+                writer = Some(ChunkWriter::create(dir, &tracker).await?);
+            }
+            if writer.is_some() {
+                pending.extend_from_slice(pkt);
+            }
+        }
+
+        if let Some(w) = writer.as_mut() {
+            w.file.write_all(&pending).await?;
+            pending.clear();
+        }
+    };
+
+    // 保留中断前已经写入的部分
+    if let Some(w) = writer {
+        w.finish(config, channel.id).await?;
+    }
+    result
+}
+
+/// 持续缓冲一个频道
+async fn buffer_channel(config: YamlConfig, channel: Channel) {
+    let dir = channel_dir(&config, channel.id);
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        warn!("创建时移目录 {:?} 失败: {}", dir, e);
+        return;
+    }
+    let chunks = load_chunks(&dir);
+    info!(
+        "开始时移缓冲: 频道 {}，已有 {} 个分块",
+        channel.name,
+        chunks.len()
+    );
+    if let Ok(mut buffers) = BUFFERS.write() {
+        buffers.insert(channel.id, chunks);
+    }
+
+    let mut channel = channel;
+    loop {
+        if let Err(e) = capture(&config, &channel, &dir).await {
+            warn!("时移频道 {} 中断: {}", channel.name, e);
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+        // 频道列表刷新后组播地址或提供频道的账号可能已经变化
+        if let Some(latest) = cached_channel(|c| c.id == channel.id) {
+            channel = latest;
+        }
+    }
+}
+
+/// 为配置的频道启动时移缓冲，频道列表刷新后为新出现的频道启动
+pub(crate) async fn timeshift_loop(config: YamlConfig) {
+    if config.timeshift.channels.is_empty() {
+        return;
+    }
+
+    let mut started = HashSet::new();
+    loop {
+        match get_cached_channels(&config.iptv).await {
+            Ok(channels) => {
+                for channel in channels
+                    .into_iter()
+                    .filter(|c| config.timeshift.includes(c.id, &c.name))
+                {
+                    if started.insert(channel.id) {
+                        tokio::spawn(buffer_channel(config.clone(), channel));
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("时移获取频道列表失败: {}", e);
+                tokio::time::sleep(RETRY_INTERVAL * 12).await;
+                continue;
+            }
+        }
+        tokio::time::sleep(CHANNEL_CACHE_DURATION).await;
+    }
+}
+
+/// 分块开头 PAT/PMT 的长度，跳到分块中间播放时需要先发送它们
+fn psi_head_len(data: &[u8]) -> usize {
+    let mut pmt_pids = Vec::new();
+    let mut len = 0;
+    for pkt in data.chunks_exact(TS_PACKET_SIZE) {
+        let pkt_pid = pid(pkt);
+        if pkt_pid == PAT_PID {
+            if let Some(section) = psi_section(pkt) {
+                pmt_pids.extend(parse_pat(section).into_iter().map(|(_, pid)| pid));
+            }
+        } else if !pmt_pids.contains(&pkt_pid) {
+            break;
+        }
+        len += TS_PACKET_SIZE;
+    }
+    len
+}
+
+/// 按分块的平均码率估算 begin 在分块文件中的位置，对齐到 TS 包
+fn seek_offset(chunk: &Chunk, len: u64, begin: i64) -> u64 {
+    let duration = (chunk.stop - chunk.start).max(1) as u128;
+    let elapsed = (begin - chunk.start).clamp(0, duration as i64) as u128;
+    let offset = (len as u128 * elapsed / duration) as u64;
+    offset - offset % TS_PACKET_SIZE as u64
+}
+
+/// 回放状态
+struct Playback {
+    channel_id: u64,
+    begin: i64,
+    end: Option<i64>,
+    /// 最后一个已打开分块的开始时间
+    cursor: i64,
+    file: Option<File>,
+    /// 等待新分块的时间
+    waited: Duration,
+    max_wait: Duration,
+}
+
+impl Playback {
+    /// 跳到当前分块中 begin 对应的位置，返回需要先发送的 PAT/PMT
+    ///
+    /// 位置按分块的平均码率估算，播放器从之后的第一个关键帧开始解码。
+    async fn seek(&mut self, chunk: &Chunk) -> Option<Bytes> {
+        let file = self.file.as_mut()?;
+        let len = file.metadata().await.ok()?.len();
+        let offset = seek_offset(chunk, len, self.begin);
+
+        let mut head = vec![0u8; TS_PACKET_SIZE * PSI_HEAD_PACKETS];
+        let n = file.read(&mut head).await.ok()?;
+        head.truncate(psi_head_len(&head[..n]));
+        if offset <= head.len() as u64 {
+            file.seek(SeekFrom::Start(head.len() as u64)).await.ok()?;
+        } else {
+            file.seek(SeekFrom::Start(offset)).await.ok()?;
+            debug!(
+                "时移频道 {} 从分块 {:?} 的第 {} 字节开始",
+                self.channel_id, chunk.path, offset
+            );
+        }
+        if head.is_empty() {
+            None
+        } else {
+            Some(Bytes::from(head))
+        }
+    }
+
+    async fn next(&mut self) -> Option<Bytes> {
+        loop {
+            if let Some(file) = self.file.as_mut() {
+                let mut buf = vec![0u8; READ_SIZE];
+                match file.read(&mut buf).await {
+                    Ok(n) if n > 0 => {
+                        buf.truncate(n);
+                        return Some(Bytes::from(buf));
+                    }
+                    Ok(_) => self.file = None,
+                    Err(e) => {
+                        warn!("读取时移分块失败: {}", e);
+                        self.file = None;
+                    }
+                }
+            }
+
+            match next_chunk(self.channel_id, self.cursor, self.begin) {
+                Some(chunk) if self.end.is_some_and(|end| chunk.start >= end) => return None,
+                Some(chunk) => {
+                    let first = self.cursor == i64::MIN;
+                    self.cursor = chunk.start;
+                    self.waited = Duration::ZERO;
+                    // 分块可能刚被清理
+                    self.file = File::open(&chunk.path).await.ok();
+                    // 第一个分块从 begin 对应的位置开始，而不是分块的开头
+                    if first && chunk.start < self.begin {
+                        if let Some(head) = self.seek(&chunk).await {
+                            return Some(head);
+                        }
+                    }
+                }
+                None => {
+                    if self
+                        .end
+                        .is_some_and(|end| end <= now_millis() - self.max_wait.as_millis() as i64)
+                        || self.waited >= self.max_wait
+                    {
+                        return None;
+                    }
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                    self.waited += POLL_INTERVAL;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimeshiftQuery {
+    pub playseek: Option<String>,
+}
+
+#[get("/timeshift/{channel_id}")]
+pub async fn timeshift(
+    config: Data<YamlConfig>,
+    path: Path<u64>,
+    query: Query<TimeshiftQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let channel_id = path.into_inner();
+    if let Some(response) = check_signature(&config, &req) {
+        return response;
+    }
+    let buffered = BUFFERS
+        .read()
+        .map(|b| b.contains_key(&channel_id))
+        .unwrap_or(false);
+    if !buffered {
+        return HttpResponse::NotFound().body("该频道没有开启时移");
+    }
+
+    let (begin, end) = match query.playseek.as_deref().map(parse_playseek) {
+        Some(Ok(range)) => range,
+        Some(Err(e)) => return HttpResponse::BadRequest().body(e.to_string()),
+        None => return HttpResponse::BadRequest().body("缺少 playseek 参数"),
+    };
+    debug!("时移回放: 频道 {} {} - {:?}", channel_id, begin, end);
+
+    let channel = cached_channel(|c| c.id == channel_id)
+        .map(|c| c.name)
+        .unwrap_or_else(|| channel_id.to_string());
+    let session = open_session(
+        &config,
+        client_ip(&req),
+        "timeshift",
+        Some(channel_id),
+        channel,
+    );
+    let guard = match session {
+        Ok(guard) => guard,
+        Err(e) => {
+            return limit_response(&e)
+                .unwrap_or_else(|| HttpResponse::InternalServerError().body(e.to_string()))
+        }
+    };
+
+    let playback = Playback {
+        channel_id,
+        begin,
+        end,
+        cursor: i64::MIN,
+        file: None,
+        waited: Duration::ZERO,
+        max_wait: Duration::from_secs(config.timeshift.chunk_duration.max(1) * 3),
+    };
+    let body = stream::unfold(playback, |mut playback| async move {
+        let data = playback.next().await?;
+        Some((Ok::<_, actix_web::Error>(data), playback))
+    });
+
+    HttpResponse::Ok()
+        .content_type("video/mp2t")
+        .streaming(tracked(body, guard))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 用 payload 填充一个 TS 包
+    fn packet(pid: u16, start: bool, payload: &[u8]) -> Vec<u8> {
+        let mut pkt = vec![
+            0x47,
+            (pid >> 8) as u8 | if start { 0x40 } else { 0 },
+            pid as u8,
+            0x10,
+        ];
+        pkt.extend_from_slice(payload);
+        pkt.resize(TS_PACKET_SIZE, 0xff);
+        pkt
+    }
+
+    /// PMT 在 0x100 的 PAT
+    fn pat() -> Vec<u8> {
+        packet(
+            PAT_PID,
+            true,
+            &[
+                0x00, 0x00, 0xb0, 0x0d, 0x00, 0x01, 0xc1, 0x00, 0x00, 0x00, 0x01, 0xe1, 0x00, 0, 0,
+                0, 0,
+            ],
+        )
+    }
+
+    fn chunk(start: i64, stop: i64) -> Chunk {
+        Chunk {
+            start,
+            stop,
+            path: PathBuf::new(),
+        }
+    }
+
+    #[test]
+    fn head_with_pat_and_pmt() {
+        let mut data = pat();
+        data.extend(packet(0x100, true, &[0x00, 0x02]));
+        data.extend(packet(0x101, true, &[0x00, 0x00, 0x01]));
+        assert_eq!(psi_head_len(&data), TS_PACKET_SIZE * 2);
+    }
+
+    #[test]
+    fn head_without_psi() {
+        let data = packet(0x101, true, &[0x00, 0x00, 0x01]);
+        assert_eq!(psi_head_len(&data), 0);
+        assert_eq!(psi_head_len(&[]), 0);
+    }
+
+    #[test]
+    fn offset_by_bitrate() {
+        let len = TS_PACKET_SIZE as u64 * 1000;
+        let chunk = chunk(10_000, 20_000);
+        assert_eq!(seek_offset(&chunk, len, 10_000), 0);
+        assert_eq!(
+            seek_offset(&chunk, len, 15_000),
+            TS_PACKET_SIZE as u64 * 500
+        );
+        // 对齐到 TS 包
+        assert_eq!(seek_offset(&chunk, len, 10_001) % TS_PACKET_SIZE as u64, 0);
+        // 超出分块范围时限制在分块内
+        assert_eq!(seek_offset(&chunk, len, 5_000), 0);
+        assert_eq!(seek_offset(&chunk, len, 30_000), len);
+    }
+
+    #[test]
+    fn offset_empty_chunk() {
+        assert_eq!(seek_offset(&chunk(10_000, 10_000), 0, 10_000), 0);
+        assert_eq!(seek_offset(&chunk(10_000, 10_000), 188, 20_000), 188);
+    }
+}
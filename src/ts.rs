@@ -0,0 +1,611 @@
+// ts.rs
+// MPEG-TS 解析工具：PAT/PMT/SDT、关键帧识别、质量统计
+use actix_web::web::Bytes;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// TS 包长度
+pub(crate) const TS_PACKET_SIZE: usize = 188;
+
+/// TS 同步字节
+pub(crate) const TS_SYNC_BYTE: u8 = 0x47;
+
+/// PAT 的 PID
+pub(crate) const PAT_PID: u16 = 0x0000;
+
+/// SDT 的 PID
+pub(crate) const SDT_PID: u16 = 0x0011;
+
+/// 空包的 PID
+pub(crate) const NULL_PID: u16 = 0x1fff;
+
+/// PCR 时钟频率
+const PCR_HZ: u64 = 27_000_000;
+
+/// PCR 回绕周期（33 位 base * 300）
+const PCR_WRAP: u64 = (1 << 33) * 300;
+
+/// PCR 间隔超过该值（秒）视为不连续
+const PCR_MAX_GAP: u64 = 1;
+
+/// 把一段数据按 188 字节切成 TS 包，跳过不对齐的部分
+pub(crate) fn packets(chunk: &[u8]) -> impl Iterator<Item = &[u8]> {
+    chunk
+        .chunks_exact(TS_PACKET_SIZE)
+        .filter(|pkt| pkt[0] == TS_SYNC_BYTE)
+}
+
+pub(crate) fn pid(pkt: &[u8]) -> u16 {
+    u16::from_be_bytes([pkt[1] & 0x1f, pkt[2]])
+}
+
+pub(crate) fn payload_unit_start(pkt: &[u8]) -> bool {
+    pkt[1] & 0x40 != 0
+}
+
+/// 自适应字段（不含长度字节）
+pub(crate) fn adaptation_field(pkt: &[u8]) -> Option<&[u8]> {
+    if pkt[3] & 0x20 == 0 {
+        return None;
+    }
+    let len = pkt[4] as usize;
+    if len == 0 || 5 + len > TS_PACKET_SIZE {
+        return None;
+    }
+    Some(&pkt[5..5 + len])
+}
+
+/// 自适应字段中的 random_access_indicator
+pub(crate) fn random_access(pkt: &[u8]) -> bool {
+    adaptation_field(pkt).is_some_and(|af| af[0] & 0x40 != 0)
+}
+
+/// 自适应字段中的 discontinuity_indicator
+pub(crate) fn discontinuity(pkt: &[u8]) -> bool {
+    adaptation_field(pkt).is_some_and(|af| af[0] & 0x80 != 0)
+}
+
+/// 自适应字段中的 PCR（27MHz）
+pub(crate) fn pcr(pkt: &[u8]) -> Option<u64> {
+    let af = adaptation_field(pkt)?;
+    if af[0] & 0x10 == 0 || af.len() < 7 {
+        return None;
+    }
+    let base = (af[1] as u64) << 25
+        | (af[2] as u64) << 17
+        | (af[3] as u64) << 9
+        | (af[4] as u64) << 1
+        | (af[5] as u64) >> 7;
+    let ext = ((af[5] & 0x01) as u64) << 8 | af[6] as u64;
+    Some(base * 300 + ext)
+}
+
+/// TS 包负载
+pub(crate) fn payload(pkt: &[u8]) -> Option<&[u8]> {
+    if pkt[3] & 0x10 == 0 {
+        return None;
+    }
+    let offset = if pkt[3] & 0x20 != 0 {
+        5 + pkt[4] as usize
+    } else {
+        4
+    };
+    pkt.get(offset..)
+}
+
+/// 取出单个包内的 PSI 段（跳过 pointer_field）
+pub(crate) fn psi_section(pkt: &[u8]) -> Option<&[u8]> {
+    if !payload_unit_start(pkt) {
+        return None;
+    }
+    let payload = payload(pkt)?;
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    if section.len() < 3 {
+        return None;
+    }
+    let section_len = (u16::from_be_bytes([section[1] & 0x0f, section[2]])) as usize;
+    section.get(..3 + section_len)
+}
+
+/// 解析 PAT，返回 (节目号, PMT PID)
+pub(crate) fn parse_pat(section: &[u8]) -> Vec<(u16, u16)> {
+    if section.first() != Some(&0x00) || section.len() < 12 {
+        return Vec::new();
+    }
+    // 去掉 8 字节头和 4 字节 CRC
+    section[8..section.len() - 4]
+        .chunks_exact(4)
+        .map(|p| {
+            (
+                u16::from_be_bytes([p[0], p[1]]),
+                u16::from_be_bytes([p[2] & 0x1f, p[3]]),
+            )
+        })
+        .filter(|(program, _)| *program != 0)
+        .collect()
+}
+
+/// PMT 中的基本流
+#[derive(Debug, Clone)]
+pub(crate) struct ElementaryStream {
+    pub(crate) pid: u16,
+    pub(crate) stream_type: u8,
+    /// ES 描述符原始数据
+    pub(crate) descriptors: Vec<u8>,
+}
+
+/// 遍历描述符，返回 (tag, 内容)
+pub(crate) fn descriptors(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let tag = *data.get(offset)?;
+        let len = *data.get(offset + 1)? as usize;
+        let body = data.get(offset + 2..offset + 2 + len)?;
+        offset += 2 + len;
+        Some((tag, body))
+    })
+}
+
+/// 解析 PMT
+pub(crate) fn parse_pmt(section: &[u8]) -> Vec<ElementaryStream> {
+    if section.first() != Some(&0x02) || section.len() < 16 {
+        return Vec::new();
+    }
+    let program_info_len = u16::from_be_bytes([section[10] & 0x0f, section[11]]) as usize;
+    let end = section.len() - 4;
+    let mut offset = 12 + program_info_len;
+    let mut streams = Vec::new();
+
+    while offset + 5 <= end {
+        let stream_type = section[offset];
+        let pid = u16::from_be_bytes([section[offset + 1] & 0x1f, section[offset + 2]]);
+        let es_info_len =
+            u16::from_be_bytes([section[offset + 3] & 0x0f, section[offset + 4]]) as usize;
+        let descriptors = section
+            .get(offset + 5..(offset + 5 + es_info_len).min(end))
+            .unwrap_or_default()
+            .to_vec();
+        streams.push(ElementaryStream {
+            pid,
+            stream_type,
+            descriptors,
+        });
+        offset += 5 + es_info_len;
+    }
+    streams
+}
+
+/// SDT 中的服务名称
+pub(crate) struct ServiceName {
+    pub(crate) provider: String,
+    pub(crate) name: String,
+}
+
+/// 解析 SDT，返回第一个服务的名称
+pub(crate) fn parse_sdt(section: &[u8]) -> Option<ServiceName> {
+    if section.first() != Some(&0x42) || section.len() < 15 {
+        return None;
+    }
+    let end = section.len() - 4;
+    let mut offset = 11;
+
+    while offset + 5 <= end {
+        let loop_len =
+            u16::from_be_bytes([section[offset + 3] & 0x0f, section[offset + 4]]) as usize;
+        let body = section.get(offset + 5..(offset + 5 + loop_len).min(end))?;
+        for (tag, data) in descriptors(body) {
+            // service_descriptor
+            if tag != 0x48 || data.len() < 3 {
+                continue;
+            }
+            let provider_len = data[1] as usize;
+            let provider = data.get(2..2 + provider_len)?;
+            let name_len = *data.get(2 + provider_len)? as usize;
+            let name = data.get(3 + provider_len..3 + provider_len + name_len)?;
+            return Some(ServiceName {
+                provider: decode_dvb_text(provider),
+                name: decode_dvb_text(name),
+            });
+        }
+        offset += 5 + loop_len;
+    }
+    None
+}
+
+/// 解码 DVB 文本，国内运营商一般使用 GB2312/GBK
+pub(crate) fn decode_dvb_text(data: &[u8]) -> String {
+    let (charset, text) = match data.first() {
+        Some(&c) if c < 0x20 => (Some(c), &data[1..]),
+        _ => (None, data),
+    };
+    match charset {
+        Some(0x15) => String::from_utf8_lossy(text).into_owned(),
+        Some(0x14) => encoding_rs::BIG5.decode(text).0.into_owned(),
+        _ => match std::str::from_utf8(text) {
+            Ok(s) => s.to_string(),
+            Err(_) => encoding_rs::GBK.decode(text).0.into_owned(),
+        },
+    }
+    .trim()
+    .to_string()
+}
+
+/// 是否为视频流
+pub(crate) fn is_video(stream_type: u8) -> bool {
+    matches!(stream_type, 0x01 | 0x02 | 0x10 | 0x1b | 0x24 | 0x42 | 0xd2)
+}
+
+/// PES 负载中是否包含关键帧（IDR / SPS / 序列头）
+fn contains_keyframe(stream_type: u8, pes: &[u8]) -> bool {
+    if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+        return false;
+    }
+    let es = match pes.get(9 + pes[8] as usize..) {
+        Some(es) => es,
+        None => return false,
+    };
+
+    es.windows(4)
+        .filter(|w| w[..3] == [0, 0, 1])
+        .any(|w| match stream_type {
+            // H.264: IDR 或 SPS
+            0x1b => matches!(w[3] & 0x1f, 5 | 7),
+            // H.265: IRAP 或 VPS/SPS
+            0x24 => matches!((w[3] >> 1) & 0x3f, 16..=21 | 32 | 33),
+            // MPEG-2 / AVS: 序列头
+            0x01 | 0x02 | 0x42 | 0xd2 => w[3] == 0xb3 || w[3] == 0xb0,
+            _ => false,
+        })
+}
+
+/// 跟踪 PAT/PMT 并识别关键帧
+#[derive(Default)]
+pub(crate) struct TsTracker {
+    pmt_pid: Option<u16>,
+    video: Option<ElementaryStream>,
+    /// 最近一个 PAT 包
+    pub(crate) pat: Option<Bytes>,
+    /// 最近一个 PMT 包
+    pub(crate) pmt: Option<Bytes>,
+}
+
+impl TsTracker {
+    /// 检查一个 TS 包，返回它是否是关键帧的开始
+    pub(crate) fn inspect(&mut self, pkt: &[u8]) -> bool {
+        let pid = pid(pkt);
+
+        if pid == PAT_PID {
+            if let Some(section) = psi_section(pkt) {
+                if let Some((_, pmt_pid)) = parse_pat(section).first() {
+                    self.pmt_pid = Some(*pmt_pid);
+                    self.pat = Some(Bytes::copy_from_slice(pkt));
+                }
+            }
+            return false;
+        }
+
+        if Some(pid) == self.pmt_pid {
+            if let Some(section) = psi_section(pkt) {
+                let streams = parse_pmt(section);
+                if !streams.is_empty() {
+                    self.video = streams.into_iter().find(|s| is_video(s.stream_type));
+                    self.pmt = Some(Bytes::copy_from_slice(pkt));
+                }
+            }
+            return false;
+        }
+
+        match &self.video {
+            Some(video) if video.pid == pid && payload_unit_start(pkt) => {
+                random_access(pkt)
+                    || payload(pkt).is_some_and(|p| contains_keyframe(video.stream_type, p))
+            }
+            _ => false,
+        }
+    }
+
+    /// PAT/PMT 是否都已经收到
+    pub(crate) fn ready(&self) -> bool {
+        self.pat.is_some() && self.pmt.is_some()
+    }
+
+    /// 最近的 PAT/PMT 包，用于拼在分片或缓存开头
+    pub(crate) fn psi_packets(&self) -> impl Iterator<Item = &Bytes> {
+        self.pat.iter().chain(self.pmt.iter())
+    }
+}
+
+/// TS 传输质量统计：连续计数器错误、PCR 不连续和 PCR 抖动
+#[derive(Default)]
+pub(crate) struct TsQuality {
+    /// PID -> 上一个连续计数器
+    continuity: HashMap<u16, u8>,
+    /// 用于统计的 PCR PID，取第一个带 PCR 的 PID
+    pcr_pid: Option<u16>,
+    last_pcr: Option<(u64, Instant)>,
+    pub(crate) packets: u64,
+    pub(crate) cc_errors: u64,
+    pub(crate) pcr_discontinuities: u64,
+    /// PCR 抖动的平滑值（毫秒）：PCR 时间差与到达时间差之差
+    pub(crate) pcr_jitter_ms: f64,
+    /// 最大 PCR 抖动（毫秒）
+    pub(crate) pcr_jitter_max_ms: f64,
+}
+
+impl TsQuality {
+    /// 检查一个 TS 包，arrival 为收到该包的时间
+    pub(crate) fn inspect(&mut self, pkt: &[u8], arrival: Instant) {
+        self.packets += 1;
+        let pid = pid(pkt);
+        if pid == NULL_PID {
+            return;
+        }
+
+        let discontinuity = discontinuity(pkt);
+        let cc = pkt[3] & 0x0f;
+        let has_payload = pkt[3] & 0x10 != 0;
+        if let Some(last) = self.continuity.insert(pid, cc) {
+            // 有负载时计数器加一，没有负载时不变；允许一个重复包
+            let expected = if has_payload { (last + 1) & 0x0f } else { last };
+            if !discontinuity && cc != expected && cc != last {
+                self.cc_errors += 1;
+            }
+        }
+
+        let pcr = match pcr(pkt) {
+            Some(pcr) => pcr,
+            None => return,
+        };
+        if *self.pcr_pid.get_or_insert(pid) != pid {
+            return;
+        }
+        if let Some((last_pcr, last_arrival)) = self.last_pcr {
+            let delta = (pcr + PCR_WRAP - last_pcr) % PCR_WRAP;
+            if discontinuity || delta > PCR_MAX_GAP * PCR_HZ {
+                self.pcr_discontinuities += 1;
+            } else {
+                let pcr_ms = delta as f64 * 1000.0 / PCR_HZ as f64;
+                let arrival_ms = arrival.duration_since(last_arrival).as_secs_f64() * 1000.0;
+                let jitter = (arrival_ms - pcr_ms).abs();
+                self.pcr_jitter_ms += (jitter - self.pcr_jitter_ms) / 16.0;
+                self.pcr_jitter_max_ms = self.pcr_jitter_max_ms.max(jitter);
+            }
+        }
+        self.last_pcr = Some((pcr, arrival));
+    }
+}
+
+/// 根据 PCR 累计已播放的媒体时长，用于断线后从正确的位置续播
+#[derive(Default)]
+pub(crate) struct PcrClock {
+    pcr_pid: Option<u16>,
+    last: Option<u64>,
+    /// 累计时长（27MHz）
+    elapsed: u64,
+}
+
+impl PcrClock {
+    pub(crate) fn push(&mut self, chunk: &[u8]) {
+        for pkt in packets(chunk) {
+            self.push_packet(pkt);
+        }
+    }
+
+    /// 处理单个 TS 包
+    pub(crate) fn push_packet(&mut self, pkt: &[u8]) {
+        let pcr = match pcr(pkt) {
+            Some(pcr) => pcr,
+            None => return,
+        };
+        if *self.pcr_pid.get_or_insert(pid(pkt)) != pid(pkt) {
+            return;
+        }
+        if let Some(last) = self.last {
+            let delta = (pcr + PCR_WRAP - last) % PCR_WRAP;
+            // 跳变不计入时长
+            if delta <= PCR_MAX_GAP * PCR_HZ {
+                self.elapsed += delta;
+            }
+        }
+        self.last = Some(pcr);
+    }
+
+    /// 是否已经收到过 PCR
+    pub(crate) fn has_pcr(&self) -> bool {
+        self.last.is_some()
+    }
+
+    /// 已播放的媒体时长（秒）
+    pub(crate) fn seconds(&self) -> f64 {
+        self.elapsed as f64 / PCR_HZ as f64
+    }
+
+    /// 已播放的媒体时长（毫秒）
+    pub(crate) fn millis(&self) -> i64 {
+        (self.elapsed / (PCR_HZ / 1000)) as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 构造 PSI 段：table_id + section_length + rest + 4 字节 CRC（不校验）
+    fn section(table_id: u8, rest: &[u8]) -> Vec<u8> {
+        let len = rest.len() + 4;
+        let mut section = vec![table_id, 0xb0 | (len >> 8) as u8, len as u8];
+        section.extend_from_slice(rest);
+        section.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        section
+    }
+
+    /// 把 PSI 段放进一个 TS 包
+    fn psi_packet(pid: u16, section: &[u8]) -> Vec<u8> {
+        let mut pkt = vec![TS_SYNC_BYTE, 0x40 | (pid >> 8) as u8, pid as u8, 0x10, 0x00];
+        pkt.extend_from_slice(section);
+        pkt.resize(TS_PACKET_SIZE, 0xff);
+        pkt
+    }
+
+    /// 只带 PCR 的 TS 包
+    fn pcr_packet(pid: u16, pcr: u64) -> Vec<u8> {
+        let (base, ext) = (pcr / 300, pcr % 300);
+        let mut pkt = vec![TS_SYNC_BYTE, (pid >> 8) as u8, pid as u8, 0x20, 183, 0x10];
+        pkt.extend_from_slice(&[
+            (base >> 25) as u8,
+            (base >> 17) as u8,
+            (base >> 9) as u8,
+            (base >> 1) as u8,
+            ((base & 1) << 7) as u8 | 0x7e | (ext >> 8) as u8,
+            ext as u8,
+        ]);
+        pkt.resize(TS_PACKET_SIZE, 0xff);
+        pkt
+    }
+
+    fn pat() -> Vec<u8> {
+        section(
+            0x00,
+            &[
+                0x00, 0x01, 0xc1, 0x00, 0x00, // transport_stream_id, version, section
+                0x00, 0x00, 0xe0, 0x10, // 节目 0: NIT
+                0x00, 0x01, 0xe1, 0x00, // 节目 1: PMT PID 0x100
+                0x00, 0x02, 0xe2, 0x00, // 节目 2: PMT PID 0x200
+            ],
+        )
+    }
+
+    #[test]
+    fn pat_from_packet() {
+        let pkt = psi_packet(PAT_PID, &pat());
+        assert_eq!(pid(&pkt), PAT_PID);
+        let section = psi_section(&pkt).unwrap();
+        assert_eq!(section, pat().as_slice());
+        assert_eq!(parse_pat(section), [(1, 0x100), (2, 0x200)]);
+    }
+
+    #[test]
+    fn psi_section_with_pointer_field() {
+        let mut pkt = psi_packet(PAT_PID, &[]);
+        pkt[4] = 2;
+        pkt.splice(7..7, pat());
+        assert_eq!(
+            parse_pat(psi_section(&pkt).unwrap()),
+            [(1, 0x100), (2, 0x200)]
+        );
+
+        // 不是段的开始
+        pkt[1] &= !0x40;
+        assert!(psi_section(&pkt).is_none());
+    }
+
+    #[test]
+    fn invalid_pat() {
+        assert!(parse_pat(&[]).is_empty());
+        assert!(parse_pat(&section(0x02, &[0; 9])).is_empty());
+    }
+
+    #[test]
+    fn pmt_streams() {
+        let pmt = section(
+            0x02,
+            &[
+                0x00, 0x01, 0xc1, 0x00, 0x00, // program_number, version, section
+                0xe1, 0x00, // PCR PID
+                0xf0, 0x03, 0x05, 0x01, 0x00, // program_info: 一个描述符
+                0x1b, 0xe1, 0x00, 0xf0, 0x00, // H.264, PID 0x100
+                0x0f, 0xe1, 0x01, 0xf0, 0x06, // AAC, PID 0x101
+                0x0a, 0x04, b'c', b'h', b'i', 0x00,
+            ],
+        );
+        let streams = parse_pmt(&pmt);
+        assert_eq!(streams.len(), 2);
+        assert_eq!((streams[0].stream_type, streams[0].pid), (0x1b, 0x100));
+        assert!(streams[0].descriptors.is_empty());
+        assert!(is_video(streams[0].stream_type));
+        assert_eq!((streams[1].stream_type, streams[1].pid), (0x0f, 0x101));
+        assert_eq!(streams[1].descriptors, [0x0a, 0x04, b'c', b'h', b'i', 0x00]);
+        assert!(!is_video(streams[1].stream_type));
+    }
+
+    #[test]
+    fn pmt_with_truncated_es_info() {
+        // ES 描述符长度超出段的范围
+        let pmt = section(
+            0x02,
+            &[
+                0x00, 0x01, 0xc1, 0x00, 0x00, 0xe1, 0x00, 0xf0, 0x00, 0x1b, 0xe1, 0x00, 0xf0, 0x20,
+                0x0a, 0x04,
+            ],
+        );
+        let streams = parse_pmt(&pmt);
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].descriptors, [0x0a, 0x04]);
+    }
+
+    #[test]
+    fn descriptor_iterator() {
+        let data = [0x0a, 0x02, 1, 2, 0x48, 0x00, 0x52, 0x05, 1];
+        let found: Vec<_> = descriptors(&data).collect();
+        // 最后一个描述符长度不够，停止
+        assert_eq!(found, [(0x0a, &[1u8, 2][..]), (0x48, &[][..])]);
+        assert_eq!(descriptors(&[]).count(), 0);
+        assert_eq!(descriptors(&[0x0a]).count(), 0);
+    }
+
+    #[test]
+    fn sdt_service_name() {
+        let name = encoding_rs::GBK.encode("广东卫视").0;
+        let mut service_descriptor = vec![0x01, 0x03];
+        service_descriptor.extend_from_slice(b"GDT");
+        service_descriptor.push(name.len() as u8);
+        service_descriptor.extend_from_slice(&name);
+
+        let mut rest = vec![
+            0x00, 0x01, 0xc1, 0x00, 0x00, // transport_stream_id, version, section
+            0x00, 0x01, 0xff, // original_network_id, reserved
+            0x00, 0x01, 0xfc, // service_id, EIT flags
+        ];
+        let loop_len = service_descriptor.len() + 2;
+        rest.extend_from_slice(&[0x80 | (loop_len >> 8) as u8, loop_len as u8]);
+        rest.extend_from_slice(&[0x48, service_descriptor.len() as u8]);
+        rest.extend_from_slice(&service_descriptor);
+
+        let service = parse_sdt(&section(0x42, &rest)).unwrap();
+        assert_eq!(service.provider, "GDT");
+        assert_eq!(service.name, "广东卫视");
+
+        assert!(parse_sdt(&section(0x46, &rest)).is_none());
+    }
+
+    #[test]
+    fn dvb_text() {
+        assert_eq!(decode_dvb_text(b"CCTV-1 "), "CCTV-1");
+        let mut utf8 = vec![0x15];
+        utf8.extend_from_slice("综合".as_bytes());
+        assert_eq!(decode_dvb_text(&utf8), "综合");
+        assert_eq!(decode_dvb_text(&encoding_rs::GBK.encode("综合").0), "综合");
+    }
+
+    #[test]
+    fn pcr_clock() {
+        let mut clock = PcrClock::default();
+        assert!(!clock.has_pcr());
+        clock.push_packet(&pcr_packet(0x100, 27_000_000));
+        assert!(clock.has_pcr());
+        // 其它 PID 的 PCR 不计入
+        clock.push_packet(&pcr_packet(0x101, 0));
+        clock.push_packet(&pcr_packet(0x100, 40_500_000));
+        assert_eq!(clock.seconds(), 0.5);
+        // 超过 PCR_MAX_GAP 的跳变不计入
+        clock.push_packet(&pcr_packet(0x100, 270_000_000));
+        clock.push_packet(&pcr_packet(0x100, 283_500_000));
+        assert_eq!(clock.seconds(), 1.0);
+        // 回绕
+        clock.push_packet(&pcr_packet(0x100, PCR_WRAP - 13_500_000));
+        clock.push_packet(&pcr_packet(0x100, 0));
+        assert_eq!(clock.seconds(), 1.5);
+        assert_eq!(clock.millis(), 1500);
+    }
+}
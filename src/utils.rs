@@ -258,4 +258,89 @@ pub fn mask_password(password: &str) -> String {
             format!("{}{}{}", start, middle_stars, end)
         }
     }
-}
\ No newline at end of file
+}
+
+/// 获取网卡的 IPv4 地址
+pub fn interface_ipv4(if_name: &str) -> Result<std::net::Ipv4Addr> {
+    let network_interfaces = local_ip_address::list_afinet_netifas()?;
+    for (name, ip) in network_interfaces.iter() {
+        if name == if_name {
+            if let std::net::IpAddr::V4(ip) = ip {
+                return Ok(*ip);
+            }
+        }
+    }
+    Err(anyhow!("网卡 {} 没有 IPv4 地址", if_name))
+}
+
+/// 获取网卡的 IPv6 地址，跳过链路本地地址（绑定时需要 scope id）
+pub fn interface_ipv6(if_name: &str) -> Result<std::net::Ipv6Addr> {
+    let network_interfaces = local_ip_address::list_afinet_netifas()?;
+    for (name, ip) in network_interfaces.iter() {
+        if name == if_name {
+            if let std::net::IpAddr::V6(ip) = ip {
+                if ip.segments()[0] & 0xffc0 != 0xfe80 {
+                    return Ok(*ip);
+                }
+            }
+        }
+    }
+    Err(anyhow!("网卡 {} 没有 IPv6 地址", if_name))
+}
+
+/// 获取网卡的 MAC 地址，格式为 FF:FF:FF:FF:FF:FF
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn interface_mac(if_name: &str) -> Result<String> {
+    let path = format!("/sys/class/net/{}/address", if_name);
+    let mac = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow!("无法读取网卡 {} 的 MAC 地址: {}", if_name, e))?;
+    let mac = mac.trim().to_uppercase();
+    if mac.is_empty() || mac == "00:00:00:00:00:00" {
+        return Err(anyhow!("网卡 {} 没有 MAC 地址", if_name));
+    }
+    Ok(mac)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub fn interface_mac(if_name: &str) -> Result<String> {
+    Err(anyhow!("当前系统不支持读取网卡 {} 的 MAC 地址", if_name))
+}
+
+/// 获取网卡的索引，用于加入 IPv6 组播
+#[cfg(unix)]
+pub fn interface_index(if_name: &str) -> Result<u32> {
+    let name = std::ffi::CString::new(if_name)?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return Err(anyhow!("网卡 {} 不存在", if_name));
+    }
+    Ok(index)
+}
+
+#[cfg(not(unix))]
+pub fn interface_index(if_name: &str) -> Result<u32> {
+    Err(anyhow!("当前系统不支持按网卡 {} 加入 IPv6 组播", if_name))
+}
+
+/// 创建发送用的 UDP socket，绑定到指定网卡，方式与 HTTP 请求使用的网卡绑定一致
+pub fn udp_socket_with_if(if_name: Option<&str>) -> Result<std::net::UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    #[allow(unused_mut)]
+    let mut local = std::net::Ipv4Addr::UNSPECIFIED;
+
+    #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+    if let Some(i) = if_name.filter(|i| !i.is_empty()) {
+        local = interface_ipv4(i)?;
+    }
+
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    if let Some(i) = if_name.filter(|i| !i.is_empty()) {
+        socket.bind_device(Some(i.as_bytes()))?;
+    }
+
+    socket.bind(&std::net::SocketAddr::from((local, 0)).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}